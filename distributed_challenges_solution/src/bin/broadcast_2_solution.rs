@@ -1,40 +1,24 @@
-use std::{
-    collections::{HashMap, HashSet},
-    time::Duration,
-};
+use std::{collections::HashSet, io::BufReader, thread::JoinHandle, time::Duration};
 
-use anyhow::Context;
-use node_driver::{Body, InputInterface, Maelstrom, Message};
-use serde::{Deserialize, Serialize};
+use anyhow::bail;
+use node_driver::{
+    anti_entropy::{AntiEntropy, AntiEntropyPayload},
+    bloom::{PushPull, PushPullPayload},
+    gossip::{Gossip, GossipPayload},
+    intset::IntSet,
+    round,
+    topology::Strategy,
+    Body, DynMessage, Maelstrom, MaelstromPayload, Message, NodeId, NodeMetadata, OutputInterface,
+};
 
 /// Defines the payload we want to send to clients in the broadcast challenge
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
+/// `MaelstromPayload` applies the `type` tag and snake case naming of the Maelstrom protocol
+#[derive(Debug, Clone, MaelstromPayload)]
 enum BroadcastPayload {
-    Topology {
-        topology: HashMap<String, Vec<String>>,
-    },
-    TopologyOk,
-    Broadcast {
-        message: usize,
-    },
+    Broadcast { message: usize },
     BroadcastOk,
     Read,
-    ReadOk {
-        messages: HashSet<usize>,
-    },
-    // we will use this message to communicate gossip in-between nodes of the cluster
-    Gossip {
-        known: HashSet<usize>,
-    },
-}
-
-/// This struct holds the internal state of our node
-struct State {
-    pub messages: HashSet<usize>,
-    /// topology is optional since we don't have it when we construct State in the first place
-    pub topology: Option<HashMap<String, Vec<String>>>,
+    ReadOk { messages: HashSet<usize> },
 }
 
 /// This defines the possible events on which our main loop can react, within our actor system
@@ -43,43 +27,183 @@ enum Event {
     Eof,
     /// this event means it's time to do some gossip
     TimeToGossip,
-    /// this event means we have received a message
-    MessageReceived(Message<BroadcastPayload>),
+    /// this event means we have received a message, either a broadcast or a gossip one
+    MessageReceived(DynMessage),
 }
 
-fn main() -> anyhow::Result<()> {
-    // init our node by getting its metadata and an output and input interface to communicate
-    // here we drop the input interface as soon as we get it to release the lock before opening a
-    // new one in a separate thread.
-    let (mut node_metadata, _, mut output) = Maelstrom::init()?;
+/// How our node shares the messages it knows with its neighbours, chosen with the BROADCAST_SYNC
+/// environment variable so we can compare them under the same workload, e.g. with the bytes sent
+/// per message type reported by node_driver's metrics.
+/// They all keep the set of messages we know for us. Except for the full state, which is the
+/// baseline, they send messages as an IntSet, which sends runs of consecutive messages as ranges.
+enum Dissemination {
+    /// `full`: we send all the messages we know to all our neighbours, whatever they know, as a
+    /// plain json array like the first version of this challenge
+    Full {
+        node_id: NodeId,
+        messages: HashSet<usize>,
+    },
+    /// `gossip` (the default): we remember which messages each neighbour acknowledged, and only
+    /// send them the ones they miss
+    Gossip(Gossip<usize, IntSet>),
+    /// `merkle`: we regularly compare a Merkle tree of our messages with a neighbour, and only
+    /// exchange the messages of the branches which differ
+    Merkle(AntiEntropy<usize, IntSet>),
+    /// `bloom`: we regularly send a neighbour a Bloom filter of our messages, it sends back the
+    /// messages which are not in it along with its own filter, and we answer with the messages
+    /// which are not in its filter
+    Bloom(PushPull<usize, IntSet>),
+}
+
+impl Dissemination {
+    fn from_env(node_metadata: &NodeMetadata) -> anyhow::Result<Self> {
+        match std::env::var("BROADCAST_SYNC").as_deref() {
+            Ok("full") => Ok(Self::Full {
+                node_id: node_metadata.node_id.clone(),
+                messages: HashSet::new(),
+            }),
+            Err(_) | Ok("gossip") => Ok(Self::Gossip(Gossip::new(node_metadata))),
+            Ok("merkle") => Ok(Self::Merkle(AntiEntropy::new(node_metadata))),
+            Ok("bloom") => Ok(Self::Bloom(PushPull::new(node_metadata))),
+            Ok(other) => {
+                bail!("Unknown BROADCAST_SYNC {other:?}, expected full, gossip, merkle or bloom")
+            }
+        }
+    }
+
+    /// the messages we know
+    fn messages(&self) -> &HashSet<usize> {
+        match self {
+            Self::Full { messages, .. } => messages,
+            Self::Gossip(gossip) => gossip.values(),
+            Self::Merkle(merkle) => merkle.values(),
+            Self::Bloom(bloom) => bloom.values(),
+        }
+    }
+
+    fn insert(&mut self, message: usize) {
+        match self {
+            Self::Full { messages, .. } => messages.insert(message),
+            Self::Gossip(gossip) => gossip.insert(message),
+            Self::Merkle(merkle) => merkle.insert(message),
+            Self::Bloom(bloom) => bloom.insert(message),
+        };
+    }
+
+    /// spawn a thread calling `on_tick` regularly, until it returns false
+    fn ticker(&self, on_tick: impl FnMut() -> bool + Send + 'static) -> JoinHandle<()> {
+        match self {
+            // full state gossip uses the same interval as the gossip
+            Self::Full { .. } => round::ticker(Duration::from_millis(250), on_tick),
+            Self::Gossip(gossip) => gossip.ticker(on_tick),
+            Self::Merkle(merkle) => merkle.ticker(on_tick),
+            Self::Bloom(bloom) => bloom.ticker(on_tick),
+        }
+    }
+
+    /// share what we know with our neighbours
+    fn tick(&mut self, neighbours: &[NodeId], output: &mut OutputInterface) -> anyhow::Result<()> {
+        match self {
+            // the full state is not acknowledged, while gossip and Bloom digests are answered
+            Self::Full { node_id, messages } => neighbours.iter().try_for_each(|n| {
+                output.send_oneway(Message {
+                    src: node_id.clone(),
+                    dst: n.clone(),
+                    body: Body::new(GossipPayload::Gossip {
+                        values: messages.clone(),
+                    }),
+                })
+            }),
+            Self::Gossip(gossip) => gossip
+                .tick(neighbours)
+                .into_iter()
+                .try_for_each(|msg| output.send_msg(msg)),
+            // the digest of the root gets no answer when the peer is in sync
+            Self::Merkle(merkle) => merkle
+                .tick(neighbours)
+                .into_iter()
+                .try_for_each(|msg| output.send_oneway(msg)),
+            Self::Bloom(bloom) => bloom
+                .tick(neighbours)
+                .into_iter()
+                .try_for_each(|msg| output.send_msg(msg)),
+        }
+    }
 
-    // init the state
-    let mut state = State {
-        messages: HashSet::new(),
-        topology: None,
-    };
+    /// handle a message from another node if it is one of ours, or give it back
+    fn handle(
+        &mut self,
+        msg: DynMessage,
+        output: &mut OutputInterface,
+    ) -> anyhow::Result<Option<DynMessage>> {
+        let msg_type = msg.payload_type().unwrap_or_default();
+        match self {
+            Self::Full { messages, .. } if GossipPayload::<HashSet<usize>>::has_type(msg_type) => {
+                // the full state needs no acknowledgement, it is sent again anyway
+                if let GossipPayload::Gossip { values } = msg
+                    .into_typed::<GossipPayload<HashSet<usize>>>()?
+                    .body
+                    .payload
+                {
+                    messages.extend(values);
+                }
+            }
+            Self::Gossip(gossip) if GossipPayload::<IntSet>::has_type(msg_type) => {
+                if let Some(reply) = gossip.handle(msg.into_typed()?).reply {
+                    output.send_msg(reply)?;
+                }
+            }
+            Self::Merkle(merkle) if AntiEntropyPayload::<IntSet>::has_type(msg_type) => {
+                for reply in merkle.handle(msg.into_typed()?).messages {
+                    output.send_msg(reply)?;
+                }
+            }
+            Self::Bloom(bloom) if PushPullPayload::<IntSet>::has_type(msg_type) => {
+                for reply in bloom.handle(msg.into_typed()?).messages {
+                    output.send_msg(reply)?;
+                }
+            }
+            _ => return Ok(Some(msg)),
+        }
+        Ok(None)
+    }
+}
 
+fn main() -> anyhow::Result<()> {
     // we will use an actor channel to handle scheduling of both gossiping and reading and
     // responding to messages.
     // create a channel that we will use to make our actors communicate
     let (tx, rx) = std::sync::mpsc::channel::<Event>();
     let tx_clone = tx.clone();
+    let tx_topology = tx.clone();
 
-    // spawn a thread generating periodic gossip events, our first actor
-    let gh = std::thread::spawn(move || loop {
-        if tx_clone.send(Event::TimeToGossip).is_err() {
-            // other side hung up, let's finish the loop
-            break;
-        };
-        std::thread::sleep(Duration::from_millis(250));
-    });
+    // init our node by getting its metadata and an output and input interface to communicate.
+    // The topology messages are acknowledged for us and stored in node_metadata, we gossip as soon
+    // as one arrives.
+    // Instead of the suggested topology, the nodes can compute their own, chosen with the
+    // NODE_DRIVER_TOPOLOGY environment variable (e.g. `tree:4`), to trade the number of messages
+    // against the latency.
+    let (node_metadata, input, mut output) = Maelstrom::builder()
+        .handle_topology(move |_| {
+            let _ = tx_topology.send(Event::TimeToGossip);
+        })
+        .topology_strategy(Strategy::from_env()?)
+        .init()?;
+    // the input interface locks stdin, which cannot be sent to another thread, so we read stdin
+    // through a BufReader instead
+    let mut input = input.with_reader(BufReader::new(std::io::stdin()));
 
-    // spawn a thread forwarding input into the channel, our second actor
-    let ih = std::thread::spawn(move || {
-        // get a new input interface, this can hang if another one already exists somewhere...
-        let mut input = InputInterface::default();
+    // the state of our node is the set of messages we know, kept by the way we share it
+    let mut dissemination = Dissemination::from_env(&node_metadata)?;
+
+    // spawn a thread generating periodic gossip events, our first actor.
+    // It stops once the other side hung up.
+    let gh = dissemination.ticker(move || tx_clone.send(Event::TimeToGossip).is_ok());
 
-        for msg in input.iter::<BroadcastPayload>() {
+    // spawn a thread forwarding input into the channel, our second actor.
+    // We read messages without a payload type, since they can be broadcast or gossip ones.
+    let ih = std::thread::spawn(move || {
+        for msg in input.iter_dyn() {
             let msg = msg.expect("Should be able to get message from stdin");
             if tx.send(Event::MessageReceived(msg)).is_err() {
                 break;
@@ -99,61 +223,34 @@ fn main() -> anyhow::Result<()> {
                 break;
             }
             Event::TimeToGossip => {
-                // it's time to gossip, let's send messages to all nodes within our reach
-                if let Some(topology) = state.topology.as_ref() {
-                    for n in topology.get(&node_metadata.node_id).context(format!(
-                        "Node {} should appear in the topology",
-                        node_metadata.node_id
-                    ))? {
-                        // for now we send the full list of messages we know, which is suboptimal
-                        output.send_msg(Message {
-                            src: node_metadata.node_id.clone(),
-                            dst: n.clone(),
-                            body: Body {
-                                msg_id: None,
-                                in_reply_to: None,
-                                payload: BroadcastPayload::Gossip {
-                                    known: state.messages.clone(),
-                                },
-                            },
-                        })?;
-                    }
-                }
-                // if we don't have the topology yet, let's skip gossiping for now.
+                // it's time to gossip, let's share what we know with all nodes within our reach.
+                // Until the topology arrives, we have no neighbour and skip gossiping.
+                dissemination.tick(&node_metadata.neighbours(), &mut output)?;
             }
             Event::MessageReceived(msg) => {
+                // gossip messages from other nodes are handled by our dissemination, which updates
+                // our known data and replies to them
+                let Some(msg) = dissemination.handle(msg, &mut output)? else {
+                    continue;
+                };
+                let msg = msg.into_typed::<BroadcastPayload>()?;
                 // match on the type of payload within the message, these are variants of the BroadcastPayload enum
                 match &msg.body.payload {
-                    BroadcastPayload::Gossip { known } => {
-                        // we received a gossip message from another node, let's update our known data
-                        state.messages = state.messages.union(known).copied().collect();
-                    }
-                    BroadcastPayload::Topology { topology } => {
-                        state.topology = Some(topology.clone());
-                        output.send_msg(msg.to_response(
-                            Some(node_metadata.get_next_msg_id()),
-                            BroadcastPayload::TopologyOk,
-                        ))?
-                    }
-                    // we are not supposed to receive a TopologyOk message, let's panic when it happens
-                    BroadcastPayload::TopologyOk => {
-                        panic!("TopologyOk message shouldn't be received by a node")
-                    }
                     BroadcastPayload::Broadcast { message } => {
-                        state.messages.insert(*message);
+                        dissemination.insert(*message);
                         output.send_msg(msg.to_response(
                             Some(node_metadata.get_next_msg_id()),
                             BroadcastPayload::BroadcastOk,
                         ))?
                     }
                     // we are not supposed to receive a BroadcastOk message, let's panic when it happens
-                    BroadcastPayload::BroadcastOk { .. } => {
+                    BroadcastPayload::BroadcastOk => {
                         panic!("BroadcastOk message shouldn't be received by a node")
                     }
                     BroadcastPayload::Read => output.send_msg(msg.to_response(
                         Some(node_metadata.get_next_msg_id()),
                         BroadcastPayload::ReadOk {
-                            messages: state.messages.clone(),
+                            messages: dissemination.messages().clone(),
                         },
                     ))?,
                     // we are not supposed to receive a ReadOk message, let's panic when it happens
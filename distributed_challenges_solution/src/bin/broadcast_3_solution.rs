@@ -0,0 +1,125 @@
+use std::{collections::HashSet, io::BufReader, time::Instant};
+
+use node_driver::{
+    reliable::{ForwardPayload, ReliableBroadcast},
+    topology::Strategy,
+    DynMessage, Maelstrom, MaelstromPayload,
+};
+
+/// Defines the payload we want to send to clients in the broadcast challenge
+/// `MaelstromPayload` applies the `type` tag and snake case naming of the Maelstrom protocol
+#[derive(Debug, Clone, MaelstromPayload)]
+enum BroadcastPayload {
+    Broadcast { message: usize },
+    BroadcastOk,
+    Read,
+    ReadOk { messages: HashSet<usize> },
+}
+
+/// This defines the possible events on which our main loop can react, within our actor system
+enum Event {
+    /// this event means there is no more input messages to read from Maelstrom
+    Eof,
+    /// this event means it's time to send again the messages our neighbours did not acknowledge
+    TimeToRetry,
+    /// this event means we have received a message, either a broadcast or a forward one
+    MessageReceived(DynMessage),
+}
+
+/// The fault tolerant broadcast: every message must reach every node, even when the network is
+/// partitioned for a while. Instead of gossiping, each node forwards the new messages it receives
+/// to its neighbours, and keeps sending them until each neighbour acknowledges them.
+fn main() -> anyhow::Result<()> {
+    // create a channel that we will use to make our actors communicate
+    let (tx, rx) = std::sync::mpsc::channel::<Event>();
+    let tx_clone = tx.clone();
+
+    // init our node, the topology messages are acknowledged for us and stored in node_metadata.
+    // The topology can also be computed by the nodes, see the NODE_DRIVER_TOPOLOGY environment
+    // variable.
+    let (node_metadata, input, mut output) = Maelstrom::builder()
+        .handle_topology(|_| {})
+        .topology_strategy(Strategy::from_env()?)
+        .init()?;
+    // the input interface locks stdin, which cannot be sent to another thread, so we read stdin
+    // through a BufReader instead
+    let mut input = input.with_reader(BufReader::new(std::io::stdin()));
+
+    // the state of our node is the set of messages we know, which the reliable broadcast keeps
+    // for us along with the messages our neighbours did not acknowledge yet
+    let mut broadcast = ReliableBroadcast::<usize>::new(&node_metadata);
+
+    // spawn a thread generating periodic retry events, our first actor.
+    // It stops once the other side hung up.
+    let rh = broadcast.ticker(move || tx_clone.send(Event::TimeToRetry).is_ok());
+
+    // spawn a thread forwarding input into the channel, our second actor
+    let ih = std::thread::spawn(move || {
+        for msg in input.iter_dyn() {
+            let msg = msg.expect("Should be able to get message from stdin");
+            if tx.send(Event::MessageReceived(msg)).is_err() {
+                break;
+            };
+        }
+        // no more messages, send EOF for proper shutdown
+        tx.send(Event::Eof).unwrap();
+    });
+
+    // main loop: for each event we receive through the channel (our last actor)
+    for event in rx {
+        match event {
+            Event::Eof => break,
+            Event::TimeToRetry => {
+                // the neighbours which did not acknowledge a message in time may be partitioned
+                // away, let's try again. The messages received before the topology are sent too.
+                for msg in broadcast.retry(Instant::now(), &node_metadata.neighbours()) {
+                    output.send_msg(msg)?;
+                }
+            }
+            Event::MessageReceived(msg)
+                if msg
+                    .payload_type()
+                    .is_some_and(ForwardPayload::<usize>::has_type) =>
+            {
+                // another node forwarded a message to us, or acknowledged one of ours: the
+                // reliable broadcast acknowledges it, and forwards it if we did not know it yet
+                let handled = broadcast.handle(msg.into_typed()?, &node_metadata.neighbours());
+                for msg in handled.messages {
+                    output.send_msg(msg)?;
+                }
+            }
+            Event::MessageReceived(msg) => {
+                let msg = msg.into_typed::<BroadcastPayload>()?;
+                match &msg.body.payload {
+                    BroadcastPayload::Broadcast { message } => {
+                        for forward in broadcast.insert(*message, &node_metadata.neighbours()) {
+                            output.send_msg(forward)?;
+                        }
+                        output.send_msg(msg.to_response(
+                            Some(node_metadata.get_next_msg_id()),
+                            BroadcastPayload::BroadcastOk,
+                        ))?
+                    }
+                    BroadcastPayload::Read => output.send_msg(msg.to_response(
+                        Some(node_metadata.get_next_msg_id()),
+                        BroadcastPayload::ReadOk {
+                            messages: broadcast.values().clone(),
+                        },
+                    ))?,
+                    // we are not supposed to receive replies to our clients' requests
+                    BroadcastPayload::BroadcastOk | BroadcastPayload::ReadOk { .. } => {
+                        panic!(
+                            "{} message shouldn't be received by a node",
+                            msg.body.payload.msg_type()
+                        )
+                    }
+                }
+            }
+        };
+    }
+
+    // let's join on both threads for proper exit
+    ih.join().unwrap();
+    rh.join().unwrap();
+    Ok(())
+}
@@ -14,7 +14,7 @@ enum EchoPayload {
 
 fn main() -> anyhow::Result<()> {
     // init our node by getting its metadata and an output and input interface to communicate
-    let (mut node_metadata, mut input, mut output) = Maelstrom::init()?;
+    let (node_metadata, mut input, mut output) = Maelstrom::init()?;
     // main loop: for each message we receive through the input interface (with a payload of type EchoPayload)
     for msg in input.iter::<EchoPayload>() {
         // if there was an error getting this message, propagate it (with the ? sigil)
@@ -28,7 +28,7 @@ fn main() -> anyhow::Result<()> {
                 body: Body {
                     msg_id: Some(node_metadata.get_next_msg_id()),
                     in_reply_to: msg.body.msg_id,
-                    payload: EchoPayload::EchoOk { echo },
+                    ..Body::new(EchoPayload::EchoOk { echo })
                 },
             })?,
             // we are not supposed to receive and EchoOk message, let's panic when it happens
@@ -15,7 +15,7 @@ enum UniqueIdPayload {
 
 fn main() -> anyhow::Result<()> {
     // init our node by getting its metadata and an output and input interface to communicate
-    let (mut node_metadata, mut input, mut output) = Maelstrom::init()?;
+    let (node_metadata, mut input, mut output) = Maelstrom::init()?;
     // main loop: for each message we receive through the input interface (with a payload of type UniqueIdPayload)
     for msg in input.iter::<UniqueIdPayload>() {
         // if there was an error getting this message, propagate it (with the ? sigil)
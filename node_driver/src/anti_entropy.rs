@@ -0,0 +1,335 @@
+//! Anti-entropy: reconciliation of the sets of values of two nodes by comparing Merkle trees.
+//!
+//! Instead of sending its values, or the values a peer may miss, an [`AntiEntropy`] regularly
+//! sends a peer the hash of all its values. The values are spread among the leaves of a binary
+//! tree, in buckets chosen by their hash, and each node of the tree holds the hash of the values
+//! below it: when the hashes of a node differ between two peers, they compare the hashes of its
+//! children, down to the leaves whose values differ. Only the values of these leaves are then
+//! exchanged, so two nodes knowing the same values exchange a single message.
+//!
+//! The exchange is made of three messages:
+//!
+//! - `merkle_digest` holds hashes of tree nodes. The peer answers with the hashes of the
+//!   descendants of the nodes whose hashes differ, or with a `merkle_request` for leaves,
+//! - `merkle_request` holds the values of the sender in some leaves, and asks for the values of
+//!   the receiver in these leaves,
+//! - `merkle_response` holds the values of these leaves the sender of the request missed.
+//!
+//! Every message but the digest of the root starting a round replies to the previous one, and
+//! sets its `in_reply_to`.
+//!
+//! The node starts the rounds and sends the messages, see [`round`](crate::round). The values are
+//! sent as a `V`, a `Vec<T>` by default, and hashed with a hash function which does not depend on
+//! the platform nor on the Rust version of the nodes.
+//!
+//! ```
+//! use node_driver::{anti_entropy::AntiEntropy, NodeMetadata};
+//!
+//! let n1 = NodeMetadata::with_cluster("n1".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+//! let n2 = NodeMetadata::with_cluster("n2".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+//! let mut sync_1: AntiEntropy<usize> = AntiEntropy::new(&n1);
+//! let mut sync_2: AntiEntropy<usize> = AntiEntropy::new(&n2);
+//! sync_1.extend(0..100);
+//! sync_2.extend(50..120);
+//!
+//! // exchange messages until both nodes are in sync
+//! let mut msgs = sync_1.tick(&["n2".into()]);
+//! while let Some(msg) = msgs.pop() {
+//!     let sync = if msg.dst == "n1" { &mut sync_1 } else { &mut sync_2 };
+//!     msgs.extend(sync.handle(msg).messages);
+//! }
+//! assert_eq!(sync_1.values(), sync_2.values());
+//! assert_eq!(sync_1.values().len(), 120);
+//!
+//! // once in sync, the digest of the root is enough
+//! assert_eq!(sync_2.handle(sync_1.tick(&["n2".into()]).remove(0)).messages.len(), 0);
+//! ```
+
+use std::{
+    collections::{HashSet, VecDeque},
+    hash::Hash,
+    marker::PhantomData,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    round::{self, stable_hash},
+    Body, MaelstromPayload, Message, MsgIdAllocator, NodeId, NodeMetadata,
+};
+
+/// Hashes are truncated to 53 bits, so that they remain exact in any json implementation
+const HASH_MASK: u64 = (1 << 53) - 1;
+
+/// The messages exchanged by [`AntiEntropy`], generic over the collection of values
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum AntiEntropyPayload<V> {
+    /// The `[index, hash]` of some nodes of the Merkle tree of the sender
+    MerkleDigest {
+        /// the nodes, the root being at index 1 and the children of `i` at `2i` and `2i + 1`
+        nodes: Vec<(usize, u64)>,
+    },
+    /// The values of the sender in some leaves, asking for the values of the receiver in them
+    MerkleRequest {
+        /// the indexes of the leaves
+        leaves: Vec<usize>,
+        /// the values
+        values: V,
+    },
+    /// The values of the leaves of a `merkle_request` which its sender did not have
+    MerkleResponse {
+        /// the values
+        values: V,
+    },
+}
+
+impl<V: Serialize + DeserializeOwned> MaelstromPayload for AntiEntropyPayload<V> {
+    const TYPES: &'static [&'static str] = &["merkle_digest", "merkle_request", "merkle_response"];
+    const REPLIES: &'static [(&'static str, &'static str)] =
+        &[("merkle_request", "merkle_response")];
+
+    fn msg_type(&self) -> &'static str {
+        match self {
+            Self::MerkleDigest { .. } => "merkle_digest",
+            Self::MerkleRequest { .. } => "merkle_request",
+            Self::MerkleResponse { .. } => "merkle_response",
+        }
+    }
+}
+
+/// What [`AntiEntropy::handle`] did with a message
+#[derive(Debug)]
+pub struct Handled<T, V = Vec<T>> {
+    /// The values the node did not know before
+    pub new_values: Vec<T>,
+    /// The messages to send back to the sender
+    pub messages: Vec<Message<AntiEntropyPayload<V>>>,
+}
+
+/// Reconciles the values of the node with its peers, see the [module docs](self).
+#[derive(Debug)]
+pub struct AntiEntropy<T, V = Vec<T>> {
+    node_id: NodeId,
+    msg_ids: MsgIdAllocator,
+    values: HashSet<T>,
+    /// the number of levels of the tree below the root
+    depth: u32,
+    /// the hash of each node of the tree, as the wrapping sum of the hashes of its values
+    tree: Vec<u64>,
+    /// the values of each leaf
+    leaves: Vec<Vec<T>>,
+    /// the number of levels a digest goes down when the hashes of a node differ
+    step: u32,
+    /// the number of rounds started, to take turns among the peers
+    round: usize,
+    interval: Duration,
+    collection: PhantomData<V>,
+}
+
+impl<T, V> AntiEntropy<T, V>
+where
+    T: Clone + Eq + Hash,
+    V: FromIterator<T> + IntoIterator<Item = T> + Serialize + DeserializeOwned,
+{
+    /// Create an anti-entropy without any value, sending messages from this node.
+    ///
+    /// By default, the tree has 256 leaves, a digest goes down 2 levels at a time, and rounds are
+    /// [`AntiEntropy::interval`]s of 250ms.
+    pub fn new(metadata: &NodeMetadata) -> Self {
+        Self {
+            node_id: metadata.node_id.clone(),
+            msg_ids: metadata.msg_id_allocator(),
+            values: HashSet::new(),
+            depth: 0,
+            tree: Vec::new(),
+            leaves: Vec::new(),
+            step: 2,
+            round: 0,
+            interval: Duration::from_millis(250),
+            collection: PhantomData,
+        }
+        .with_depth(8)
+    }
+
+    /// Use a tree with `2^depth` leaves: more leaves take more rounds to compare, but exchange
+    /// fewer values which are already known. Every node must use the same depth.
+    pub fn with_depth(mut self, depth: u32) -> Self {
+        self.depth = depth.clamp(1, 20);
+        self.tree = vec![0; 2 << self.depth];
+        self.leaves = vec![Vec::new(); 1 << self.depth];
+        for value in std::mem::take(&mut self.values) {
+            self.insert(value);
+        }
+        self
+    }
+
+    /// Go down this many levels of the tree at once when the hashes of a node differ, instead of
+    /// comparing its children only: fewer round trips for larger digests
+    pub fn with_step(mut self, levels: u32) -> Self {
+        self.step = levels.max(1);
+        self
+    }
+
+    /// Set the time between two rounds, used by [`AntiEntropy::ticker`]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The time between two rounds
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The values known by the node
+    pub fn values(&self) -> &HashSet<T> {
+        &self.values
+    }
+
+    /// Add a value, returning whether it was not known yet
+    pub fn insert(&mut self, value: T) -> bool {
+        if self.values.contains(&value) {
+            return false;
+        }
+        let hash = stable_hash(&value);
+        let bucket = hash as usize & (self.leaves.len() - 1);
+        let mut node = self.leaves.len() + bucket;
+        while node > 0 {
+            self.tree[node] = self.tree[node].wrapping_add(hash);
+            node /= 2;
+        }
+        self.leaves[bucket].push(value.clone());
+        self.values.insert(value)
+    }
+
+    /// Add several values
+    pub fn extend(&mut self, values: impl IntoIterator<Item = T>) {
+        for value in values {
+            self.insert(value);
+        }
+    }
+
+    /// Start a round: build the digest of the root of the tree for one of the `peers`, taking
+    /// turns among them.
+    ///
+    /// A peer in sync does not answer this digest, so it has no `msg_id` and should be sent with
+    /// [`OutputInterface::send_oneway`](crate::OutputInterface::send_oneway).
+    pub fn tick(&mut self, peers: &[NodeId]) -> Vec<Message<AntiEntropyPayload<V>>> {
+        let peers: Vec<&NodeId> = peers.iter().filter(|&peer| *peer != self.node_id).collect();
+        if peers.is_empty() {
+            return Vec::new();
+        }
+        self.round += 1;
+        let peer = peers[self.round % peers.len()].clone();
+        let nodes = vec![(1, self.tree[1] & HASH_MASK)];
+        vec![Message {
+            src: self.node_id.clone(),
+            dst: peer,
+            body: Body::new(AntiEntropyPayload::MerkleDigest { nodes }),
+        }]
+    }
+
+    /// Handle a message of a peer, and build the messages continuing the exchange
+    pub fn handle(&mut self, msg: Message<AntiEntropyPayload<V>>) -> Handled<T, V> {
+        let mut new_values = Vec::new();
+        let mut messages = Vec::new();
+        match msg.body.payload {
+            AntiEntropyPayload::MerkleDigest { nodes } => {
+                let (mut digest, mut leaves) = (Vec::new(), Vec::new());
+                for (node, hash) in nodes {
+                    if node == 0 || node >= self.tree.len() || self.tree[node] & HASH_MASK == hash {
+                        continue;
+                    }
+                    if node >= self.leaves.len() {
+                        leaves.push(node);
+                    } else {
+                        digest.extend(self.descendants(node));
+                    }
+                }
+                if !digest.is_empty() {
+                    let payload = AntiEntropyPayload::MerkleDigest { nodes: digest };
+                    messages.push(self.message(msg.src.clone(), msg.body.msg_id, payload));
+                }
+                if !leaves.is_empty() {
+                    let values = self.values_of(&leaves).cloned().collect();
+                    let payload = AntiEntropyPayload::MerkleRequest { leaves, values };
+                    messages.push(self.message(msg.src.clone(), msg.body.msg_id, payload));
+                }
+            }
+            AntiEntropyPayload::MerkleRequest { leaves, values } => {
+                let received: HashSet<T> = values.into_iter().collect();
+                let missing: V = self
+                    .values_of(&leaves)
+                    .filter(|&value| !received.contains(value))
+                    .cloned()
+                    .collect();
+                new_values.extend(
+                    received
+                        .into_iter()
+                        .filter(|value| self.insert(value.clone())),
+                );
+                messages.push(self.message(
+                    msg.src.clone(),
+                    msg.body.msg_id,
+                    AntiEntropyPayload::MerkleResponse { values: missing },
+                ));
+            }
+            AntiEntropyPayload::MerkleResponse { values } => {
+                new_values.extend(
+                    values
+                        .into_iter()
+                        .filter(|value| self.insert(value.clone())),
+                );
+            }
+        }
+        Handled {
+            new_values,
+            messages,
+        }
+    }
+
+    /// Spawn a thread calling `on_tick` at each [`AntiEntropy::interval`], see [`round::ticker`]
+    pub fn ticker(&self, on_tick: impl FnMut() -> bool + Send + 'static) -> JoinHandle<()> {
+        round::ticker(self.interval, on_tick)
+    }
+
+    /// The `[index, hash]` of the nodes `step` levels below `node`, or of the leaves below it
+    fn descendants(&self, node: usize) -> Vec<(usize, u64)> {
+        let mut nodes = VecDeque::from([node]);
+        for _ in 0..self.step {
+            if nodes[0] >= self.leaves.len() {
+                break;
+            }
+            nodes = nodes
+                .into_iter()
+                .flat_map(|node| [2 * node, 2 * node + 1])
+                .collect();
+        }
+        nodes
+            .into_iter()
+            .map(|node| (node, self.tree[node] & HASH_MASK))
+            .collect()
+    }
+
+    /// The values of the given leaves
+    fn values_of<'a>(&'a self, leaves: &'a [usize]) -> impl Iterator<Item = &'a T> + 'a {
+        leaves
+            .iter()
+            .filter_map(|&leaf| leaf.checked_sub(self.leaves.len()))
+            .filter_map(|leaf| self.leaves.get(leaf))
+            .flatten()
+    }
+
+    fn message(
+        &self,
+        dst: NodeId,
+        in_reply_to: Option<usize>,
+        payload: AntiEntropyPayload<V>,
+    ) -> Message<AntiEntropyPayload<V>> {
+        round::message(&self.node_id, dst, &self.msg_ids, in_reply_to, payload)
+    }
+}
@@ -0,0 +1,25 @@
+//! Run a router forwarding messages between nodes connected through Unix domain sockets.
+//!
+//! Usage: `node_router SOCKET_PATH`
+//!
+//! Nodes then connect to it with `NODE_DRIVER_ROUTER=SOCKET_PATH` and `NODE_DRIVER_NODE_ID` set,
+//! see `node_driver::transport::UnixTransport`.
+
+#[cfg(unix)]
+fn main() -> anyhow::Result<()> {
+    use anyhow::Context;
+    use node_driver::transport::UnixRouter;
+
+    node_driver::logging::init();
+    let path = std::env::args()
+        .nth(1)
+        .context("Usage: node_router SOCKET_PATH")?;
+    let router = UnixRouter::bind(path)?;
+    tracing::info!(path = %router.path().display(), "router listening");
+    router.run()
+}
+
+#[cfg(not(unix))]
+fn main() -> anyhow::Result<()> {
+    anyhow::bail!("node_router requires Unix domain sockets")
+}
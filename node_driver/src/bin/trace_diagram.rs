@@ -0,0 +1,57 @@
+//! Turn a trace of messages (one json message per line, read from stdin) into a sequence diagram
+//! written to stdout.
+//!
+//! Usage: `trace_diagram [--mermaid | --svg] [--node ID]... [--type TYPE]... < trace.jsonl`
+//!
+//! Nodes write such a trace when `NODE_DRIVER_TRACE` holds the path of a file, see
+//! `node_driver::trace`.
+
+use anyhow::{bail, Context};
+use node_driver::diagram::{read_trace, SequenceDiagram};
+
+const USAGE: &str = "\
+Usage: trace_diagram [--mermaid | --svg] [--node ID]... [--type TYPE]... < trace.jsonl
+
+Run the nodes with NODE_DRIVER_TRACE=/path/to/trace.jsonl to record the trace: every node
+appends the messages it receives, and the ones it sends to clients and services.";
+
+enum Format {
+    Mermaid,
+    Svg,
+}
+
+fn main() -> anyhow::Result<()> {
+    let mut format = Format::Mermaid;
+    let mut nodes = Vec::new();
+    let mut types = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--mermaid" => format = Format::Mermaid,
+            "--svg" => format = Format::Svg,
+            "--node" => nodes.push(args.next().context("--node expects a node id")?),
+            "--type" => types.push(args.next().context("--type expects a message type")?),
+            "-h" | "--help" => {
+                eprintln!("{USAGE}");
+                return Ok(());
+            }
+            other => bail!("Unknown argument {other}\n{USAGE}"),
+        }
+    }
+
+    let trace = read_trace(std::io::stdin().lock())?;
+    let mut diagram = SequenceDiagram::new(trace);
+    if !nodes.is_empty() {
+        diagram = diagram.filter_nodes(&nodes);
+    }
+    if !types.is_empty() {
+        diagram = diagram.filter_types(&types);
+    }
+
+    match format {
+        Format::Mermaid => print!("{}", diagram.to_mermaid()),
+        Format::Svg => print!("{}", diagram.to_svg()),
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+//! Turn a trace of messages (one json message per line, read from stdin) into a Jepsen EDN
+//! history of client operations written to stdout.
+//!
+//! Traces carry no timestamps, so each message is given the time of its position in the trace.
+//!
+//! Usage: `trace_history < trace.jsonl > history.edn`
+//!
+//! Nodes write such a trace when `NODE_DRIVER_TRACE` holds the path of a file, see
+//! `node_driver::trace`.
+
+use anyhow::bail;
+use node_driver::{diagram::read_trace, history::HistoryRecorder};
+
+const USAGE: &str = "\
+Usage: trace_history < trace.jsonl > history.edn
+
+Run the nodes with NODE_DRIVER_TRACE=/path/to/trace.jsonl to record the trace: every node
+appends the messages it receives, and the ones it sends to clients and services.";
+
+fn main() -> anyhow::Result<()> {
+    if let Some(arg) = std::env::args().nth(1) {
+        if arg == "-h" || arg == "--help" {
+            eprintln!("{USAGE}");
+            return Ok(());
+        }
+        bail!("Unknown argument {arg}\n{USAGE}");
+    }
+    let mut history = HistoryRecorder::new();
+    for (time, msg) in read_trace(std::io::stdin().lock())?.iter().enumerate() {
+        history.record_at(msg, time as u64);
+    }
+    history.write_edn(std::io::stdout().lock())?;
+    Ok(())
+}
@@ -0,0 +1,380 @@
+//! Push-pull gossip with Bloom filters, exchanging only the values a peer does not have.
+//!
+//! On each round, a [`PushPull`] sends one of its peers a `bloom_digest`: a [`BloomFilter`] of the
+//! values it knows. The peer answers with a `bloom_values` message holding the values which are
+//! not in the filter, along with a filter of its own values, to which the first node answers with
+//! the values missing from it. A single exchange brings both nodes in sync, except for the few
+//! values a filter wrongly claims to hold, its false positives: each filter uses a new seed, so
+//! these values are exchanged on a later round.
+//!
+//! A filter takes about 10 bits per value for a false positive rate of 1%, which is much less
+//! than the values themselves, but it is sent even when the peers are in sync. The values are sent
+//! as a `V`, a `Vec<T>` by default, and the node drives the exchange as described in
+//! [`round`](crate::round).
+//!
+//! ```
+//! use node_driver::{bloom::PushPull, NodeMetadata};
+//!
+//! let n1 = NodeMetadata::with_cluster("n1".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+//! let n2 = NodeMetadata::with_cluster("n2".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+//! let mut sync_1: PushPull<usize> = PushPull::new(&n1);
+//! let mut sync_2: PushPull<usize> = PushPull::new(&n2);
+//! sync_1.extend(0..100);
+//! sync_2.extend(50..120);
+//!
+//! // a digest, the values n1 misses with the digest of n2, then the values n2 misses
+//! let digest = sync_1.tick(&["n2".into()]).remove(0);
+//! let pushed = sync_2.handle(digest).messages.remove(0);
+//! let pulled = sync_1.handle(pushed).messages.remove(0);
+//! assert!(sync_2.handle(pulled).messages.is_empty());
+//!
+//! // up to the false positives of the filters
+//! assert!(sync_1.values().len() >= 115);
+//! assert!(sync_2.values().len() >= 115);
+//! ```
+
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::{
+    de::DeserializeOwned, de::Error as _, Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    round::{self, stable_hash, StableHasher},
+    MaelstromPayload, Message, MsgIdAllocator, NodeId, NodeMetadata,
+};
+
+/// The maximum number of hashes of a value in a filter, which bounds the work of a lookup
+const MAX_HASHES: u32 = 32;
+
+/// A probabilistic set of values: it may wrongly claim to contain a value, but never misses one
+/// it contains.
+///
+/// It is serialized with its bits as an hexadecimal string. Values are hashed with a hash function
+/// which does not depend on the platform nor on the Rust version, so filters can be sent to nodes
+/// built differently.
+///
+/// ```
+/// use node_driver::bloom::BloomFilter;
+///
+/// let mut filter = BloomFilter::new(100, 0.01, 7);
+/// filter.insert(&"a");
+/// assert!(filter.contains(&"a"));
+///
+/// let json = serde_json::to_string(&filter).unwrap();
+/// let parsed: BloomFilter = serde_json::from_str(&json).unwrap();
+/// assert!(parsed.contains(&"a"));
+/// assert_eq!(parsed, filter);
+///
+/// let too_many_hashes = r#"{"bits":"00","hashes":4294967295,"seed":1}"#;
+/// assert!(serde_json::from_str::<BloomFilter>(too_many_hashes).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+    seed: u32,
+}
+
+impl BloomFilter {
+    /// Create an empty filter sized to hold `expected` values with the given rate of false
+    /// positives, hashing values with the given seed
+    pub fn new(expected: usize, false_positive_rate: f64, seed: u32) -> Self {
+        let expected = expected.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-expected * rate.ln() / (ln2 * ln2)).ceil().max(8.0) as usize;
+        let hashes = ((bits as f64 / expected) * ln2)
+            .round()
+            .clamp(1.0, MAX_HASHES as f64) as u32;
+        Self {
+            bits: vec![0; bits.div_ceil(8)],
+            hashes,
+            seed,
+        }
+    }
+
+    /// The number of bits of the filter
+    pub fn len_bits(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    /// Add a value
+    pub fn insert<T: Hash + ?Sized>(&mut self, value: &T) {
+        for bit in self.bit_indexes(value) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Whether the value may have been added: always true if it was, and sometimes if it was not
+    pub fn contains<T: Hash + ?Sized>(&self, value: &T) -> bool {
+        self.bit_indexes(value)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// The bits of a value, by double hashing
+    fn bit_indexes<T: Hash + ?Sized>(&self, value: &T) -> impl Iterator<Item = usize> {
+        let hash = |salt: u32| {
+            let mut hasher = StableHasher::default();
+            (self.seed, salt).hash(&mut hasher);
+            value.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (first, second) = (hash(0), hash(1) | 1);
+        let len = self.len_bits() as u64;
+        (0..self.hashes as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % len) as usize)
+    }
+}
+
+/// The serialized form of a [`BloomFilter`]
+#[derive(Serialize, Deserialize)]
+struct BloomFilterRepr {
+    bits: String,
+    hashes: u32,
+    seed: u32,
+}
+
+impl Serialize for BloomFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut bits = String::with_capacity(self.bits.len() * 2);
+        for byte in &self.bits {
+            let _ = write!(bits, "{byte:02x}");
+        }
+        BloomFilterRepr {
+            bits,
+            hashes: self.hashes,
+            seed: self.seed,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BloomFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = BloomFilterRepr::deserialize(deserializer)?;
+        if repr.bits.is_empty() || repr.bits.len() % 2 != 0 {
+            return Err(D::Error::custom("Invalid Bloom filter"));
+        }
+        // a peer could otherwise make every lookup arbitrarily long
+        if !(1..=MAX_HASHES).contains(&repr.hashes) {
+            return Err(D::Error::custom(format!(
+                "Invalid number of hashes {} in a Bloom filter, expected 1 to {MAX_HASHES}",
+                repr.hashes
+            )));
+        }
+        let bits = (0..repr.bits.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(repr.bits.get(i..i + 2).unwrap_or_default(), 16))
+            .collect::<Result<_, _>>()
+            .map_err(|e| D::Error::custom(format!("Invalid Bloom filter bits: {e}")))?;
+        Ok(Self {
+            bits,
+            hashes: repr.hashes,
+            seed: repr.seed,
+        })
+    }
+}
+
+/// The messages exchanged by [`PushPull`], generic over the collection of values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum PushPullPayload<V> {
+    /// A filter of the values known by the sender
+    BloomDigest {
+        /// the filter
+        filter: BloomFilter,
+    },
+    /// The values missing from the filter of a `bloom_digest` or `bloom_values` message
+    BloomValues {
+        /// the values
+        values: V,
+        /// a filter of the values known by the sender, to get back the values it misses
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filter: Option<BloomFilter>,
+    },
+}
+
+impl<V: Serialize + DeserializeOwned> MaelstromPayload for PushPullPayload<V> {
+    const TYPES: &'static [&'static str] = &["bloom_digest", "bloom_values"];
+    const REPLIES: &'static [(&'static str, &'static str)] = &[("bloom_digest", "bloom_values")];
+
+    fn msg_type(&self) -> &'static str {
+        match self {
+            Self::BloomDigest { .. } => "bloom_digest",
+            Self::BloomValues { .. } => "bloom_values",
+        }
+    }
+}
+
+/// What [`PushPull::handle`] did with a message
+#[derive(Debug)]
+pub struct Handled<T, V = Vec<T>> {
+    /// The values the node did not know before
+    pub new_values: Vec<T>,
+    /// The messages to send back to the sender
+    pub messages: Vec<Message<PushPullPayload<V>>>,
+}
+
+/// Synchronizes the values of the node with its peers, see the [module docs](self).
+#[derive(Debug)]
+pub struct PushPull<T, V = Vec<T>> {
+    node_id: NodeId,
+    msg_ids: MsgIdAllocator,
+    values: HashSet<T>,
+    false_positive_rate: f64,
+    /// the number of filters built, to give each its own seed
+    filters: u32,
+    /// the number of rounds started, to take turns among the peers
+    round: usize,
+    interval: Duration,
+    collection: PhantomData<V>,
+}
+
+impl<T, V> PushPull<T, V>
+where
+    T: Clone + Eq + Hash,
+    V: FromIterator<T> + IntoIterator<Item = T> + Serialize + DeserializeOwned,
+{
+    /// Create a push-pull gossip without any value, sending messages from this node.
+    ///
+    /// By default, filters have a false positive rate of 1%, and rounds are
+    /// [`PushPull::interval`]s of 250ms.
+    pub fn new(metadata: &NodeMetadata) -> Self {
+        Self {
+            node_id: metadata.node_id.clone(),
+            msg_ids: metadata.msg_id_allocator(),
+            values: HashSet::new(),
+            false_positive_rate: 0.01,
+            filters: 0,
+            round: 0,
+            interval: Duration::from_millis(250),
+            collection: PhantomData,
+        }
+    }
+
+    /// Set the rate of false positives of the filters: lower rates make larger filters, but fewer
+    /// values wrongly left out of an exchange
+    pub fn with_false_positive_rate(mut self, rate: f64) -> Self {
+        self.false_positive_rate = rate;
+        self
+    }
+
+    /// Set the time between two rounds, used by [`PushPull::ticker`]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The time between two rounds
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The values known by the node
+    pub fn values(&self) -> &HashSet<T> {
+        &self.values
+    }
+
+    /// Add a value, returning whether it was not known yet
+    pub fn insert(&mut self, value: T) -> bool {
+        self.values.insert(value)
+    }
+
+    /// Add several values
+    pub fn extend(&mut self, values: impl IntoIterator<Item = T>) {
+        self.values.extend(values);
+    }
+
+    /// Start a round: build a `bloom_digest` for one of the `peers`, taking turns among them
+    pub fn tick(&mut self, peers: &[NodeId]) -> Vec<Message<PushPullPayload<V>>> {
+        let peers: Vec<&NodeId> = peers.iter().filter(|&peer| *peer != self.node_id).collect();
+        if peers.is_empty() {
+            return Vec::new();
+        }
+        self.round += 1;
+        let peer = peers[self.round % peers.len()].clone();
+        let filter = self.filter();
+        vec![self.message(peer, None, PushPullPayload::BloomDigest { filter })]
+    }
+
+    /// Handle a message of a peer, and build the values it misses
+    pub fn handle(&mut self, msg: Message<PushPullPayload<V>>) -> Handled<T, V> {
+        let mut new_values = Vec::new();
+        let mut messages = Vec::new();
+        match msg.body.payload {
+            PushPullPayload::BloomDigest { filter } => {
+                // send what the peer misses, and ask for what we miss
+                let values = self.missing_from(&filter).into_iter().collect();
+                let filter = Some(self.filter());
+                let payload = PushPullPayload::BloomValues { values, filter };
+                messages.push(self.message(msg.src, msg.body.msg_id, payload));
+            }
+            PushPullPayload::BloomValues { values, filter } => {
+                new_values.extend(
+                    values
+                        .into_iter()
+                        .filter(|value| self.insert(value.clone())),
+                );
+                if let Some(filter) = filter {
+                    let values = self.missing_from(&filter);
+                    if !values.is_empty() {
+                        let values = values.into_iter().collect();
+                        let payload = PushPullPayload::BloomValues {
+                            values,
+                            filter: None,
+                        };
+                        messages.push(self.message(msg.src, msg.body.msg_id, payload));
+                    }
+                }
+            }
+        }
+        Handled {
+            new_values,
+            messages,
+        }
+    }
+
+    /// Spawn a thread calling `on_tick` at each [`PushPull::interval`], see [`round::ticker`]
+    pub fn ticker(&self, on_tick: impl FnMut() -> bool + Send + 'static) -> JoinHandle<()> {
+        round::ticker(self.interval, on_tick)
+    }
+
+    /// A filter of the known values, with a new seed
+    fn filter(&mut self) -> BloomFilter {
+        self.filters = self.filters.wrapping_add(1);
+        // nodes building filters at the same time should not share their false positives
+        let seed = self.filters ^ (stable_hash(&self.node_id) as u32);
+        let mut filter = BloomFilter::new(self.values.len(), self.false_positive_rate, seed);
+        for value in &self.values {
+            filter.insert(value);
+        }
+        filter
+    }
+
+    /// The known values which are not in the filter
+    fn missing_from(&self, filter: &BloomFilter) -> Vec<T> {
+        self.values
+            .iter()
+            .filter(|&value| !filter.contains(value))
+            .cloned()
+            .collect()
+    }
+
+    fn message(
+        &self,
+        dst: NodeId,
+        in_reply_to: Option<usize>,
+        payload: PushPullPayload<V>,
+    ) -> Message<PushPullPayload<V>> {
+        round::message(&self.node_id, dst, &self.msg_ids, in_reply_to, payload)
+    }
+}
@@ -0,0 +1,293 @@
+//! Sequence diagrams built from recorded [`Message`] traces.
+//!
+//! Maelstrom renders a `messages.svg` Lamport diagram at the end of each run. When running nodes
+//! outside of Maelstrom, this module lets you rebuild similar diagrams from a trace of messages,
+//! either as a [Mermaid](https://mermaid.js.org/syntax/sequenceDiagram.html) sequence diagram
+//! (handy to paste in the tutorial) or as a standalone SVG file.
+//!
+//! A trace is simply a list of messages in the order they were sent, usually stored as one json
+//! message per line (see [`read_trace`]), which the nodes write when [`trace`](crate::trace) is
+//! configured.
+//!
+//! ```
+//! use node_driver::diagram::{read_trace, SequenceDiagram};
+//!
+//! let trace = r#"{"src":"c1","dest":"n0","body":{"type":"echo","msg_id":1,"echo":"hi"}}
+//! {"src":"n0","dest":"c1","body":{"type":"echo_ok","msg_id":1,"in_reply_to":1,"echo":"hi"}}"#;
+//!
+//! let diagram = SequenceDiagram::new(read_trace(trace.as_bytes()).unwrap());
+//! let mermaid = diagram.to_mermaid();
+//! assert!(mermaid.contains("c1->>n0: echo"));
+//! assert!(mermaid.contains("n0-->>c1: echo_ok"));
+//! assert!(diagram.to_svg().starts_with("<svg"));
+//! ```
+
+use std::{collections::HashMap, fmt::Write as _, io::BufRead};
+
+use anyhow::Context;
+use serde_json::Value;
+
+use crate::{Message, NodeId};
+
+/// Colors used for message types, picked by hashing the type name.
+const PALETTE: [(u8, u8, u8); 8] = [
+    (31, 119, 180),
+    (255, 127, 14),
+    (44, 160, 44),
+    (214, 39, 40),
+    (148, 103, 189),
+    (140, 86, 75),
+    (227, 119, 194),
+    (23, 190, 207),
+];
+
+/// Maximum number of characters of the payload shown next to the message type.
+const MAX_LABEL_LEN: usize = 48;
+
+// SVG layout, in pixels
+const LANE_WIDTH: usize = 160;
+const HEADER_HEIGHT: usize = 40;
+const TICK_HEIGHT: usize = 28;
+const MARGIN: usize = 20;
+
+/// Read a trace made of one json [`Message`] per line, skipping blank lines.
+pub fn read_trace<R: BufRead>(reader: R) -> anyhow::Result<Vec<Message<Value>>> {
+    reader
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(i, line)| {
+            let line = line.context("Reading trace")?;
+            serde_json::from_str(&line).with_context(|| format!("Parsing trace line {}", i + 1))
+        })
+        .collect()
+}
+
+/// A sequence diagram of the messages exchanged between nodes and clients.
+///
+/// Messages are drawn in the order they appear in the trace. The diagram can be narrowed down to
+/// some nodes or some message types with [`SequenceDiagram::filter_nodes`] and
+/// [`SequenceDiagram::filter_types`].
+pub struct SequenceDiagram {
+    messages: Vec<Message<Value>>,
+}
+
+impl SequenceDiagram {
+    /// Build a diagram from a trace of messages
+    pub fn new(messages: impl IntoIterator<Item = Message<Value>>) -> Self {
+        Self {
+            messages: messages.into_iter().collect(),
+        }
+    }
+
+    /// Only keep messages sent or received by one of the given nodes
+    pub fn filter_nodes<S: AsRef<str>>(mut self, nodes: &[S]) -> Self {
+        self.messages.retain(|msg| {
+            nodes
+                .iter()
+                .any(|n| msg.src == n.as_ref() || msg.dst == n.as_ref())
+        });
+        self
+    }
+
+    /// Only keep messages whose body `type` is one of the given types
+    pub fn filter_types<S: AsRef<str>>(mut self, types: &[S]) -> Self {
+        self.messages
+            .retain(|msg| types.iter().any(|t| t.as_ref() == message_type(msg)));
+        self
+    }
+
+    /// The participants of the diagram: clients first, then nodes, then anything else (services)
+    fn participants(&self) -> Vec<&NodeId> {
+        let mut participants: Vec<&NodeId> = Vec::new();
+        for msg in &self.messages {
+            for id in [&msg.src, &msg.dst] {
+                if !participants.contains(&id) {
+                    participants.push(id);
+                }
+            }
+        }
+        participants.sort();
+        participants
+    }
+
+    /// Render the diagram as a Mermaid `sequenceDiagram`.
+    ///
+    /// Each message is wrapped in a `rect` block colored according to its type, and replies are
+    /// drawn with dotted arrows.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("sequenceDiagram\n");
+        for participant in self.participants() {
+            let _ = writeln!(out, "    participant {participant}");
+        }
+        for msg in &self.messages {
+            let (r, g, b) = type_color(message_type(msg));
+            let arrow = if msg.body.in_reply_to.is_some() {
+                "-->>"
+            } else {
+                "->>"
+            };
+            let _ = writeln!(out, "    rect rgba({r}, {g}, {b}, 0.2)");
+            let _ = writeln!(
+                out,
+                "    {}{arrow}{}: {}",
+                msg.src,
+                msg.dst,
+                escape_mermaid(&label(msg))
+            );
+            let _ = writeln!(out, "    end");
+        }
+        out
+    }
+
+    /// Render the diagram as a standalone SVG document.
+    ///
+    /// Like Maelstrom's `messages.svg`, the vertical axis is a Lamport clock: sending a message
+    /// ticks the sender's clock, and receiving it moves the recipient's clock past the send time.
+    /// Arrows are therefore slanted when a message is received after the recipient did other work.
+    pub fn to_svg(&self) -> String {
+        let participants = self.participants();
+        let lane_x = |id: &NodeId| {
+            let index = participants.iter().position(|p| *p == id).unwrap_or(0);
+            MARGIN + index * LANE_WIDTH + LANE_WIDTH / 2
+        };
+
+        // compute send and receive Lamport timestamps of every message
+        let mut clocks: HashMap<&NodeId, usize> = HashMap::new();
+        let mut events = Vec::with_capacity(self.messages.len());
+        for msg in &self.messages {
+            let send = clocks.get(&msg.src).copied().unwrap_or(0) + 1;
+            clocks.insert(&msg.src, send);
+            let recv = clocks.get(&msg.dst).copied().unwrap_or(0).max(send) + 1;
+            clocks.insert(&msg.dst, recv);
+            events.push((msg, send, recv));
+        }
+        let max_clock = clocks.values().copied().max().unwrap_or(0);
+
+        let width = 2 * MARGIN + participants.len() * LANE_WIDTH;
+        let height = HEADER_HEIGHT + (max_clock + 1) * TICK_HEIGHT + 2 * MARGIN;
+        let y = |clock: usize| HEADER_HEIGHT + MARGIN + clock * TICK_HEIGHT;
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="monospace" font-size="11">"#
+        );
+        out.push_str("<defs>\n");
+        for (i, (r, g, b)) in PALETTE.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                r#"<marker id="arrow-{i}" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="rgb({r},{g},{b})"/></marker>"#
+            );
+        }
+        out.push_str("</defs>\n");
+        let _ = writeln!(
+            out,
+            r#"<rect width="{width}" height="{height}" fill="white"/>"#
+        );
+
+        for participant in &participants {
+            let x = lane_x(participant);
+            let _ = writeln!(
+                out,
+                r#"<text x="{x}" y="{}" text-anchor="middle" font-weight="bold">{}</text>"#,
+                MARGIN + 12,
+                escape_xml(participant.as_str())
+            );
+            let _ = writeln!(
+                out,
+                r##"<line x1="{x}" y1="{}" x2="{x}" y2="{}" stroke="#999"/>"##,
+                HEADER_HEIGHT,
+                height - MARGIN
+            );
+        }
+
+        for (msg, send, recv) in events {
+            let color_index = type_color_index(message_type(msg));
+            let (r, g, b) = PALETTE[color_index];
+            let (x1, y1, x2, y2) = (lane_x(&msg.src), y(send), lane_x(&msg.dst), y(recv));
+            let dash = if msg.body.in_reply_to.is_some() {
+                r#" stroke-dasharray="4 3""#
+            } else {
+                ""
+            };
+            let _ = writeln!(
+                out,
+                r#"<line x1="{x1}" y1="{y1}" x2="{x2}" y2="{y2}" stroke="rgb({r},{g},{b})" stroke-width="1.5"{dash} marker-end="url(#arrow-{color_index})"/>"#
+            );
+            let _ = writeln!(
+                out,
+                r#"<text x="{}" y="{}" text-anchor="middle" fill="rgb({r},{g},{b})">{}</text>"#,
+                (x1 + x2) / 2,
+                (y1 + y2) / 2 - 4,
+                escape_xml(&label(msg))
+            );
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+/// Extract the `type` field of a message body, or an empty string if there is none.
+fn message_type(msg: &Message<Value>) -> &str {
+    msg.body
+        .payload
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+/// Build the text displayed along a message arrow: its type followed by a truncated payload.
+fn label(msg: &Message<Value>) -> String {
+    let mut fields = match &msg.body.payload {
+        Value::Object(map) => map.clone(),
+        _ => serde_json::Map::new(),
+    };
+    fields.remove("type");
+    let mut label = message_type(msg).to_string();
+    if !fields.is_empty() {
+        let payload = Value::Object(fields).to_string();
+        label.push(' ');
+        if payload.chars().count() > MAX_LABEL_LEN {
+            label.extend(payload.chars().take(MAX_LABEL_LEN - 3));
+            label.push_str("...");
+        } else {
+            label.push_str(&payload);
+        }
+    }
+    label
+}
+
+/// Requests and their `_ok` replies share the same color.
+fn type_color_index(message_type: &str) -> usize {
+    let base = message_type.strip_suffix("_ok").unwrap_or(message_type);
+    // FNV-1a, stable across runs unlike the std hasher
+    let hash = base.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    (hash % PALETTE.len() as u64) as usize
+}
+
+fn type_color(message_type: &str) -> (u8, u8, u8) {
+    PALETTE[type_color_index(message_type)]
+}
+
+/// Mermaid uses `;` as a statement separator and `#` for entity codes in message texts.
+fn escape_mermaid(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut out, c| {
+            match c {
+                '#' => out.push_str("#35;"),
+                ';' => out.push_str("#59;"),
+                c => out.push(c),
+            }
+            out
+        })
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
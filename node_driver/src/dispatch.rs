@@ -0,0 +1,190 @@
+//! Dispatch the messages of a node to handlers of different payload types.
+//!
+//! [`InputInterface::iter`] reads messages of a single payload type, which forces a node to merge
+//! every message it understands in one enum. A [`Dispatcher`] instead reads [`DynMessage`]s and
+//! hands each of them to the first handler that accepts it:
+//!
+//! - handlers registered with [`Dispatcher::on`] accept the messages of the types of their
+//!   [`MaelstromPayload`],
+//! - handlers registered with [`Dispatcher::on_type`] accept the messages of a given `type`,
+//! - the [`Dispatcher::fallback`] handler gets the messages no other handler accepted, which are
+//!   otherwise dropped with a warning.
+//!
+//! Every handler gets a mutable access to a context shared by all the handlers, typically holding
+//! the state of the node and its [`OutputInterface`](crate::OutputInterface).
+//!
+//! ```
+//! use std::io::Cursor;
+//! use node_driver::{dispatch::Dispatcher, InputInterface, MaelstromPayload};
+//!
+//! #[derive(Debug, MaelstromPayload)]
+//! enum Broadcast {
+//!     Broadcast { message: usize },
+//!     BroadcastOk,
+//! }
+//!
+//! #[derive(Default)]
+//! struct Node {
+//!     messages: Vec<usize>,
+//!     errors: usize,
+//!     unknown: usize,
+//! }
+//!
+//! let lines = r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":1,"message":4}}
+//! {"src":"lin-kv","dest":"n1","body":{"type":"error","in_reply_to":3,"code":20}}
+//! {"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":2,"message":"four"}}
+//! {"src":"c1","dest":"n1","body":{"type":"topology","msg_id":3}}"#;
+//!
+//! let mut node = Node::default();
+//! Dispatcher::new()
+//!     .on::<Broadcast>(|node: &mut Node, msg| {
+//!         if let Broadcast::Broadcast { message } = msg.body.payload {
+//!             node.messages.push(message);
+//!         }
+//!         Ok(())
+//!     })
+//!     .on_type("error", |node, _| {
+//!         node.errors += 1;
+//!         Ok(())
+//!     })
+//!     .fallback(|node, _| {
+//!         node.unknown += 1;
+//!         Ok(())
+//!     })
+//!     .run(&mut node, &mut InputInterface::new(Cursor::new(lines)))
+//!     .unwrap();
+//!
+//! // the invalid broadcast message is dropped, and not handed to the fallback
+//! assert_eq!(node.messages, vec![4]);
+//! assert_eq!((node.errors, node.unknown), (1, 1));
+//! ```
+
+use std::io::BufRead;
+
+use crate::{DynMessage, InputInterface, MaelstromPayload, Message};
+
+type DynHandler<'a, C> = Box<dyn FnMut(&mut C, DynMessage) -> anyhow::Result<()> + 'a>;
+
+/// How a handler accepts messages
+enum Handler<'a, C> {
+    /// accepts the messages whose type is one for which the function returns `true`
+    Typed(fn(&str) -> bool, DynHandler<'a, C>),
+    /// accepts the messages of the given type
+    ByType(String, DynHandler<'a, C>),
+}
+
+/// Dispatches messages to the handlers registered for them, see the [module docs](self).
+///
+/// `C` is the context passed to every handler.
+pub struct Dispatcher<'a, C> {
+    handlers: Vec<Handler<'a, C>>,
+    fallback: Option<DynHandler<'a, C>>,
+}
+
+impl<'a, C> Dispatcher<'a, C> {
+    /// Create a dispatcher without any handler
+    pub fn new() -> Self {
+        Self {
+            handlers: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Register a handler for the messages whose `type` is one of the [`MaelstromPayload::TYPES`]
+    /// of `P`.
+    ///
+    /// A message of a type known to `P` but with invalid fields cannot be handed to this handler:
+    /// it is dropped with a warning.
+    pub fn on<P>(
+        mut self,
+        mut handler: impl FnMut(&mut C, Message<P>) -> anyhow::Result<()> + 'a,
+    ) -> Self
+    where
+        P: MaelstromPayload,
+    {
+        self.handlers.push(Handler::Typed(
+            P::has_type,
+            Box::new(move |ctx, msg| {
+                let src = msg.src.clone();
+                match msg.into_typed::<P>() {
+                    Ok(typed) => handler(ctx, typed),
+                    Err(e) => {
+                        tracing::warn!(%src, error = %e, "invalid message, dropping it");
+                        Ok(())
+                    }
+                }
+            }),
+        ));
+        self
+    }
+
+    /// Register a handler for the messages of the given `type`, whatever their payload
+    pub fn on_type(
+        mut self,
+        msg_type: impl Into<String>,
+        handler: impl FnMut(&mut C, DynMessage) -> anyhow::Result<()> + 'a,
+    ) -> Self {
+        self.handlers
+            .push(Handler::ByType(msg_type.into(), Box::new(handler)));
+        self
+    }
+
+    /// Set the handler of the messages that no other handler accepts
+    pub fn fallback(
+        mut self,
+        handler: impl FnMut(&mut C, DynMessage) -> anyhow::Result<()> + 'a,
+    ) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Hand a message to the first handler accepting it, in the order they were registered, and
+    /// return the result of this handler.
+    pub fn dispatch(&mut self, ctx: &mut C, msg: DynMessage) -> anyhow::Result<()> {
+        for handler in &mut self.handlers {
+            match handler {
+                Handler::Typed(has_type, handler) => {
+                    if msg.payload_type().is_some_and(*has_type) {
+                        return handler(ctx, msg);
+                    }
+                }
+                Handler::ByType(msg_type, handler) => {
+                    if msg.payload_type() == Some(msg_type.as_str()) {
+                        return handler(ctx, msg);
+                    }
+                }
+            }
+        }
+        match &mut self.fallback {
+            Some(fallback) => fallback(ctx, msg),
+            None => {
+                tracing::warn!(
+                    src = %msg.src,
+                    msg_type = msg.payload_type().unwrap_or_default(),
+                    "no handler for message, dropping it"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Dispatch every message of the input interface until it reaches EOF.
+    ///
+    /// This stops at the first error, whether it comes from reading a message or from a handler.
+    pub fn run<R: BufRead>(
+        &mut self,
+        ctx: &mut C,
+        input: &mut InputInterface<R>,
+    ) -> anyhow::Result<()> {
+        for msg in input.iter_dyn() {
+            self.dispatch(ctx, msg?)?;
+        }
+        Ok(())
+    }
+}
+
+impl<C> Default for Dispatcher<'_, C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,161 @@
+//! Messages whose payload type is not known in advance.
+//!
+//! A node often handles several families of messages: requests from clients, gossip from other
+//! nodes, replies from Maelstrom services or errors. Instead of merging them all in a single
+//! payload enum, messages can be read as [`DynMessage`]s, dispatched according to their `type` or
+//! `in_reply_to`, and only then converted to the right [`Message<P>`] with
+//! [`Message::into_typed`]:
+//!
+//! ```
+//! use std::io::Cursor;
+//! use node_driver::{DynMessage, InputInterface, Message};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Debug, Serialize, Deserialize)]
+//! #[serde(tag = "type", rename_all = "snake_case")]
+//! enum Broadcast {
+//!     Broadcast { message: usize },
+//! }
+//!
+//! #[derive(Debug, Serialize, Deserialize)]
+//! #[serde(tag = "type", rename_all = "snake_case")]
+//! enum Kv {
+//!     ReadOk { value: usize },
+//! }
+//!
+//! let lines = r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","msg_id":1,"message":4}}
+//! {"src":"lin-kv","dest":"n1","body":{"type":"read_ok","in_reply_to":3,"value":2}}"#;
+//! let mut input = InputInterface::new(Cursor::new(lines));
+//! for msg in input.iter_dyn() {
+//!     let msg: DynMessage = msg.unwrap();
+//!     match msg.payload_type() {
+//!         Some("broadcast") => {
+//!             let msg: Message<Broadcast> = msg.into_typed().unwrap();
+//!             assert!(matches!(msg.body.payload, Broadcast::Broadcast { message: 4 }));
+//!         }
+//!         Some("read_ok") => {
+//!             assert_eq!(msg.body.in_reply_to, Some(3));
+//!             let msg: Message<Kv> = msg.into_typed().unwrap();
+//!             assert!(matches!(msg.body.payload, Kv::ReadOk { value: 2 }));
+//!         }
+//!         other => panic!("unexpected message type {other:?}"),
+//!     }
+//! }
+//! ```
+
+use std::io::BufRead;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Body, InputInterface, Message, NodeId};
+
+/// A [`Message`] whose payload has not been given a type yet
+pub type DynMessage = Message<Value>;
+
+impl DynMessage {
+    /// The `type` of the payload, if it has one
+    pub fn payload_type(&self) -> Option<&str> {
+        self.body.payload.get("type").and_then(Value::as_str)
+    }
+
+    /// Convert the payload to the type `P`.
+    ///
+    /// Like when a [`Message<P>`] is deserialized, the fields which `P` ignores are moved to
+    /// [`Body::extra`].
+    pub fn into_typed<P>(self) -> anyhow::Result<Message<P>>
+    where
+        P: Serialize + DeserializeOwned,
+    {
+        let msg_type = self.payload_type().unwrap_or_default().to_string();
+        let (payload, mut extra) = crate::split_extra(self.body.payload).with_context(|| {
+            format!(
+                "Message of type {msg_type:?} cannot be converted to {}",
+                std::any::type_name::<P>()
+            )
+        })?;
+        extra.extend(self.body.extra);
+        Ok(Message {
+            src: self.src,
+            dst: self.dst,
+            body: Body {
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+                payload,
+                extra,
+            },
+        })
+    }
+}
+
+impl<P: Serialize> Message<P> {
+    /// Convert the payload to a [`serde_json::Value`], e.g. to store messages of different types
+    /// together
+    pub fn into_dyn(self) -> anyhow::Result<DynMessage> {
+        let payload =
+            serde_json::to_value(self.body.payload).context("Serializing message body")?;
+        Ok(Message {
+            src: self.src,
+            dst: self.dst,
+            body: Body {
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+                payload,
+                extra: self.body.extra,
+            },
+        })
+    }
+}
+
+/// The routing fields of a message, parsed without looking at its payload.
+///
+/// This is cheaper than parsing a whole [`DynMessage`] when only the type or the sender of a
+/// message matter, like to pick which handler should parse it:
+///
+/// ```
+/// use node_driver::Envelope;
+///
+/// let line = r#"{"src":"n2","dest":"n1","body":{"type":"gossip","in_reply_to":7,"known":[1,2,3]}}"#;
+/// let envelope = Envelope::parse(line).unwrap();
+/// assert_eq!(envelope.src, "n2");
+/// assert_eq!(envelope.body.msg_type, "gossip");
+/// assert_eq!(envelope.body.in_reply_to, Some(7));
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Envelope {
+    /// The identifier of the Maelstrom node that send the message
+    pub src: NodeId,
+    /// The identifier of the recipient Maelstrom node
+    #[serde(rename = "dest")]
+    pub dst: NodeId,
+    /// The protocol fields of the body
+    pub body: EnvelopeBody,
+}
+
+/// The protocol fields of the body of an [`Envelope`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnvelopeBody {
+    /// The `type` of the payload, empty if there is none
+    #[serde(rename = "type", default)]
+    pub msg_type: String,
+    /// An optional id for the message
+    pub msg_id: Option<usize>,
+    /// An optional id identifying the message this one is replying to
+    pub in_reply_to: Option<usize>,
+}
+
+impl Envelope {
+    /// Parse the envelope of a message serialized as json
+    pub fn parse(msg: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(msg).context("Message envelope cannot be deserialized.")
+    }
+}
+
+impl<R: BufRead> InputInterface<R> {
+    /// Obtain an iterator over [`DynMessage`]s, to dispatch them before choosing their payload
+    /// type. See [`InputInterface::iter`].
+    pub fn iter_dyn(&mut self) -> impl Iterator<Item = anyhow::Result<DynMessage>> + '_ {
+        self.iter()
+    }
+}
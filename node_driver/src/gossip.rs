@@ -0,0 +1,350 @@
+//! Gossip of a growing set of values between the nodes, sending each peer only what it misses.
+//!
+//! A [`Gossip`] holds the values known by the node, and for each peer the values it may miss: the
+//! values learned since they were last sent to the peer, its delta, and the values sent to it but
+//! not acknowledged with a `gossip_ok` yet. On each round, started with [`Gossip::tick`], it sends
+//! to its peers their delta, and sends values again if they are not acknowledged after a few
+//! rounds. A value the peer acknowledged, or sent to the node, is forgotten for this peer, so the
+//! work of a round only depends on what changed since the previous ones.
+//!
+//! The node decides when rounds happen, typically every [`Gossip::interval`] with
+//! [`Gossip::ticker`], and hands the gossip messages it receives to [`Gossip::handle`], see
+//! [`round`](crate::round).
+//!
+//! The values of a `gossip` message are sent as a `V`, a `Vec<T>` unless another collection is
+//! chosen, like an [`IntSet`](crate::intset::IntSet) which is much more compact for runs of
+//! integers.
+//!
+//! ```
+//! use node_driver::{gossip::{Gossip, GossipPayload}, Message, NodeId, NodeMetadata};
+//!
+//! let n1 = NodeMetadata::with_cluster("n1".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+//! let n2 = NodeMetadata::with_cluster("n2".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+//! let mut gossip_1 = Gossip::new(&n1);
+//! let mut gossip_2 = Gossip::new(&n2);
+//! let peers: Vec<NodeId> = vec!["n2".into()];
+//!
+//! gossip_1.insert(1);
+//! gossip_1.insert(2);
+//! let msgs: Vec<Message<GossipPayload<Vec<usize>>>> = gossip_1.tick(&peers);
+//! assert_eq!(msgs.len(), 1);
+//!
+//! // n2 learns the values and acknowledges them
+//! let handled = gossip_2.handle(msgs.into_iter().next().unwrap());
+//! assert_eq!(handled.new_values.len(), 2);
+//! gossip_1.handle(handled.reply.unwrap());
+//!
+//! // n2 has everything n1 knows, there is nothing left to send
+//! assert!(gossip_1.tick(&peers).is_empty());
+//! gossip_1.insert(3);
+//! let msgs = gossip_1.tick(&peers);
+//! assert!(matches!(&msgs[0].body.payload, GossipPayload::Gossip { values } if values == &[3]));
+//! ```
+//!
+//! With a fanout, only some of the peers missing values are gossiped with on each round, taking
+//! turns:
+//!
+//! ```
+//! use node_driver::{gossip::{Gossip, GossipPayload}, Message, NodeId, NodeMetadata};
+//!
+//! let ids: Vec<NodeId> = vec!["n1".into(), "n2".into(), "n3".into(), "n4".into()];
+//! let mut gossip = Gossip::new(&NodeMetadata::with_cluster("n1".into(), ids.clone(), 1).unwrap())
+//!     .with_fanout(1);
+//! gossip.insert(1);
+//!
+//! let mut gossiped: Vec<NodeId> = (0..3)
+//!     .flat_map(|_| {
+//!         let msgs: Vec<Message<GossipPayload<Vec<usize>>>> = gossip.tick(&ids[1..]);
+//!         assert_eq!(msgs.len(), 1);
+//!         msgs.into_iter().map(|msg| msg.dst)
+//!     })
+//!     .collect();
+//! gossiped.sort();
+//! assert_eq!(gossiped, ["n2", "n3", "n4"]);
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    marker::PhantomData,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{round, MaelstromPayload, Message, MsgIdAllocator, NodeId, NodeMetadata};
+
+/// The messages exchanged by [`Gossip`], generic over the collection of gossiped values
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum GossipPayload<V> {
+    /// Values the receiver may not know yet
+    Gossip {
+        /// the values
+        values: V,
+    },
+    /// Acknowledges the values of the `gossip` message it replies to
+    GossipOk,
+}
+
+impl<V: Serialize + DeserializeOwned> MaelstromPayload for GossipPayload<V> {
+    const TYPES: &'static [&'static str] = &["gossip", "gossip_ok"];
+    const REPLIES: &'static [(&'static str, &'static str)] = &[("gossip", "gossip_ok")];
+
+    fn msg_type(&self) -> &'static str {
+        match self {
+            Self::Gossip { .. } => "gossip",
+            Self::GossipOk => "gossip_ok",
+        }
+    }
+}
+
+/// What [`Gossip::handle`] did with a message
+#[derive(Debug)]
+pub struct Handled<T, V = Vec<T>> {
+    /// The values the node did not know before
+    pub new_values: Vec<T>,
+    /// The acknowledgement to send back, if the message was a `gossip` one
+    pub reply: Option<Message<GossipPayload<V>>>,
+}
+
+/// What the node knows about a peer
+#[derive(Debug)]
+struct Peer<T> {
+    /// the values the peer may miss and which were not sent to it yet
+    delta: HashSet<T>,
+    /// the values sent to the peer and not acknowledged yet, with the round they were sent in
+    unacked: HashMap<T, u64>,
+}
+
+impl<T: Clone + Eq + Hash> Peer<T> {
+    /// A peer which may miss every value
+    fn new(values: &HashSet<T>) -> Self {
+        Self {
+            delta: values.clone(),
+            unacked: HashMap::new(),
+        }
+    }
+
+    /// Forget a value the peer is known to have
+    fn has(&mut self, value: &T) {
+        self.delta.remove(value);
+        self.unacked.remove(value);
+    }
+}
+
+/// A `gossip` message waiting for its acknowledgement
+#[derive(Debug)]
+struct InFlight<T> {
+    peer: NodeId,
+    values: Vec<T>,
+    round: u64,
+}
+
+/// Gossips a set of values with the peers of the node, see the [module docs](self).
+#[derive(Debug)]
+pub struct Gossip<T, V = Vec<T>> {
+    node_id: NodeId,
+    msg_ids: MsgIdAllocator,
+    values: HashSet<T>,
+    peers: HashMap<NodeId, Peer<T>>,
+    /// the `gossip` messages sent, by `msg_id`
+    in_flight: HashMap<usize, InFlight<T>>,
+    round: u64,
+    interval: Duration,
+    fanout: Option<usize>,
+    retry_after: u64,
+    collection: PhantomData<V>,
+}
+
+impl<T, V> Gossip<T, V>
+where
+    T: Clone + Eq + Hash,
+    V: FromIterator<T> + IntoIterator<Item = T> + Serialize + DeserializeOwned,
+{
+    /// Create a gossip without any value, sending messages from this node.
+    ///
+    /// By default, rounds are [`Gossip::interval`]s of 250ms, every peer is gossiped with on each
+    /// round, and values are sent again after 2 rounds without acknowledgement.
+    pub fn new(metadata: &NodeMetadata) -> Self {
+        Self {
+            node_id: metadata.node_id.clone(),
+            msg_ids: metadata.msg_id_allocator(),
+            values: HashSet::new(),
+            peers: HashMap::new(),
+            in_flight: HashMap::new(),
+            round: 0,
+            interval: Duration::from_millis(250),
+            fanout: None,
+            retry_after: 2,
+            collection: PhantomData,
+        }
+    }
+
+    /// Set the time between two rounds, used by [`Gossip::ticker`]
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Only gossip with up to `fanout` peers on each round, taking turns among the peers which
+    /// miss values
+    pub fn with_fanout(mut self, fanout: usize) -> Self {
+        self.fanout = Some(fanout.max(1));
+        self
+    }
+
+    /// Send the values not acknowledged by a peer again after this many rounds
+    pub fn with_retry_after(mut self, rounds: u64) -> Self {
+        self.retry_after = rounds.max(1);
+        self
+    }
+
+    /// The time between two rounds
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The values known by the node
+    pub fn values(&self) -> &HashSet<T> {
+        &self.values
+    }
+
+    /// Add a value to gossip, returning whether it was not known yet
+    pub fn insert(&mut self, value: T) -> bool {
+        if !self.values.insert(value.clone()) {
+            return false;
+        }
+        for peer in self.peers.values_mut() {
+            peer.delta.insert(value.clone());
+        }
+        true
+    }
+
+    /// The state of a peer, which may miss every value when it is new
+    fn peer(&mut self, peer: NodeId) -> &mut Peer<T> {
+        let values = &self.values;
+        self.peers.entry(peer).or_insert_with(|| Peer::new(values))
+    }
+
+    /// Start a round: build the `gossip` messages sending their delta to some of the `peers`.
+    ///
+    /// Every message has its `msg_id` set, so it must be sent as is for its acknowledgement to be
+    /// recognized.
+    pub fn tick(&mut self, peers: &[NodeId]) -> Vec<Message<GossipPayload<V>>> {
+        self.round += 1;
+        let (round, retry_after) = (self.round, self.retry_after);
+        // a lost acknowledgement will never arrive, the values are sent again anyway
+        self.in_flight
+            .retain(|_, in_flight| in_flight.round + 2 * retry_after > round);
+
+        let node_id = self.node_id.clone();
+        // the peers which may miss values
+        let missing = peers
+            .iter()
+            .filter(|&peer| *peer != node_id)
+            .filter(|&peer| {
+                let state = self.peer(peer.clone());
+                !state.delta.is_empty()
+                    || state
+                        .unacked
+                        .values()
+                        .any(|&sent| sent + retry_after <= round)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // take turns among the peers when gossiping with only some of them
+        let fanout = self.fanout.unwrap_or(missing.len()).min(missing.len());
+        let skip = if fanout == 0 {
+            0
+        } else {
+            (round as usize * fanout) % missing.len()
+        };
+        missing
+            .into_iter()
+            .cycle()
+            .skip(skip)
+            .take(fanout)
+            .map(|peer| {
+                let state = self.peers.get_mut(&peer).expect("the peer was just added");
+                let mut values: Vec<T> = state.delta.drain().collect();
+                values.extend(
+                    state
+                        .unacked
+                        .iter()
+                        .filter(|(_, &sent)| sent + retry_after <= round)
+                        .map(|(value, _)| value.clone()),
+                );
+                for value in &values {
+                    state.unacked.insert(value.clone(), round);
+                }
+                let msg = round::message(
+                    &self.node_id,
+                    peer.clone(),
+                    &self.msg_ids,
+                    None,
+                    GossipPayload::Gossip {
+                        values: values.iter().cloned().collect(),
+                    },
+                );
+                let msg_id = msg.body.msg_id.expect("the message has a msg_id");
+                self.in_flight.insert(
+                    msg_id,
+                    InFlight {
+                        peer,
+                        values,
+                        round,
+                    },
+                );
+                msg
+            })
+            .collect()
+    }
+
+    /// Handle a gossip message: learn the values of a `gossip` message and build its
+    /// acknowledgement, or record the values acknowledged by a `gossip_ok` message
+    pub fn handle(&mut self, mut msg: Message<GossipPayload<V>>) -> Handled<T, V> {
+        match std::mem::replace(&mut msg.body.payload, GossipPayload::GossipOk) {
+            GossipPayload::Gossip { values } => {
+                let mut new_values = Vec::new();
+                for value in values {
+                    if self.insert(value.clone()) {
+                        new_values.push(value.clone());
+                    }
+                    // the peer has the value, no need to ever send it back
+                    self.peer(msg.src.clone()).has(&value);
+                }
+                let reply = msg.to_response(Some(self.msg_ids.next_id()), GossipPayload::GossipOk);
+                Handled {
+                    new_values,
+                    reply: Some(reply),
+                }
+            }
+            GossipPayload::GossipOk => {
+                let in_flight = msg
+                    .body
+                    .in_reply_to
+                    .and_then(|msg_id| self.in_flight.remove(&msg_id));
+                if let Some(InFlight { peer, values, .. }) = in_flight {
+                    let peer = self.peer(peer);
+                    for value in &values {
+                        peer.has(value);
+                    }
+                }
+                Handled {
+                    new_values: Vec::new(),
+                    reply: None,
+                }
+            }
+        }
+    }
+
+    /// Spawn a thread calling `on_tick` every [`Gossip::interval`], until it returns `false`, see
+    /// [`round::ticker`]
+    pub fn ticker(&self, on_tick: impl FnMut() -> bool + Send + 'static) -> JoinHandle<()> {
+        round::ticker(self.interval, on_tick)
+    }
+}
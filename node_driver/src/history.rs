@@ -0,0 +1,339 @@
+//! Client operation histories in Jepsen's EDN format.
+//!
+//! Maelstrom records every client operation in a `history.edn` file, which is then checked by
+//! [Knossos](https://github.com/jepsen-io/knossos) or [Elle](https://github.com/jepsen-io/elle).
+//! [`HistoryRecorder`] rebuilds the same kind of history from the messages exchanged with clients,
+//! so runs made without Maelstrom can be fed to the same checkers or compared to Maelstrom's own.
+//!
+//! ```
+//! use node_driver::{history::HistoryRecorder, Message};
+//!
+//! let request: Message<serde_json::Value> = serde_json::from_str(
+//!     r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","msg_id":1,"message":3}}"#,
+//! ).unwrap();
+//! let reply: Message<serde_json::Value> = serde_json::from_str(
+//!     r#"{"src":"n0","dest":"c1","body":{"type":"broadcast_ok","in_reply_to":1}}"#,
+//! ).unwrap();
+//!
+//! let mut history = HistoryRecorder::new();
+//! history.record_at(&request, 10);
+//! history.record_at(&reply, 25);
+//!
+//! let mut edn = Vec::new();
+//! history.write_edn(&mut edn).unwrap();
+//! assert_eq!(
+//!     String::from_utf8(edn).unwrap(),
+//!     "{:type :invoke, :f :broadcast, :value 3, :process 0, :time 10, :index 0}\n\
+//!      {:type :ok, :f :broadcast, :value 3, :process 0, :time 25, :index 1}\n"
+//! );
+//! ```
+
+use std::{collections::HashMap, fmt, io::Write, time::Instant};
+
+use serde_json::Value;
+
+use crate::{Message, NodeId, NodeKind};
+
+/// Maelstrom error codes after which the operation may or may not have taken place.
+///
+/// Those are recorded as `:info` instead of `:fail`, see the
+/// [error documentation](https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors).
+const INDEFINITE_ERROR_CODES: [i64; 2] = [0, 13];
+
+/// An [EDN](https://github.com/edn-format/edn) value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edn {
+    /// `nil`
+    Nil,
+    /// `true` or `false`
+    Bool(bool),
+    /// An integer
+    Int(i64),
+    /// A floating point number
+    Float(f64),
+    /// A string
+    Str(String),
+    /// A keyword, written with a leading colon
+    Keyword(String),
+    /// A vector `[a b c]`
+    Vector(Vec<Edn>),
+    /// A map `{k v, k v}`, keeping the insertion order
+    Map(Vec<(Edn, Edn)>),
+}
+
+impl Edn {
+    /// Build a keyword, converting `snake_case` names to the idiomatic `kebab-case`
+    pub fn keyword(name: &str) -> Self {
+        Edn::Keyword(name.replace('_', "-"))
+    }
+}
+
+impl From<&Value> for Edn {
+    /// Json objects become maps with keyword keys, other values are converted as-is.
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => Edn::Nil,
+            Value::Bool(b) => Edn::Bool(*b),
+            Value::Number(n) => n
+                .as_i64()
+                .map(Edn::Int)
+                .unwrap_or_else(|| Edn::Float(n.as_f64().unwrap_or(f64::NAN))),
+            Value::String(s) => Edn::Str(s.clone()),
+            Value::Array(values) => Edn::Vector(values.iter().map(Edn::from).collect()),
+            Value::Object(map) => Edn::Map(
+                map.iter()
+                    .map(|(k, v)| (Edn::keyword(k), Edn::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Edn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edn::Nil => write!(f, "nil"),
+            Edn::Bool(b) => write!(f, "{b}"),
+            Edn::Int(i) => write!(f, "{i}"),
+            Edn::Float(x) if x.is_finite() => write!(f, "{x:?}"),
+            Edn::Float(x) if x.is_nan() => write!(f, "##NaN"),
+            Edn::Float(x) if *x > 0.0 => write!(f, "##Inf"),
+            Edn::Float(_) => write!(f, "##-Inf"),
+            Edn::Str(s) => {
+                write!(f, "\"")?;
+                for c in s.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c => write!(f, "{c}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Edn::Keyword(k) => write!(f, ":{k}"),
+            Edn::Vector(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, "]")
+            }
+            Edn::Map(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key} {value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// The `:type` of an operation in a Jepsen history
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpType {
+    /// The client started the operation
+    Invoke,
+    /// The operation completed successfully
+    Ok,
+    /// The operation definitely did not take place
+    Fail,
+    /// The operation may or may not have taken place
+    Info,
+}
+
+impl fmt::Display for OpType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OpType::Invoke => ":invoke",
+            OpType::Ok => ":ok",
+            OpType::Fail => ":fail",
+            OpType::Info => ":info",
+        })
+    }
+}
+
+/// A single entry of a Jepsen history
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    /// Whether this is an invocation or a completion
+    pub op_type: OpType,
+    /// The function being invoked, e.g. `broadcast` or `read`
+    pub f: String,
+    /// The argument of the invocation, or the result of the completion
+    pub value: Edn,
+    /// The logical process (client) performing the operation
+    pub process: usize,
+    /// Nanoseconds elapsed since the start of the history
+    pub time: u64,
+    /// Position of the operation in the history
+    pub index: usize,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{:type {}, :f {}, :value {}, :process {}, :time {}, :index {}}}",
+            self.op_type,
+            Edn::keyword(&self.f),
+            self.value,
+            self.process,
+            self.time,
+            self.index
+        )
+    }
+}
+
+/// Builds a Jepsen history by observing the messages exchanged between clients and nodes.
+///
+/// Every message sent by a client (see [`NodeKind::Client`]) with a `msg_id` is recorded as an
+/// `:invoke`, and the matching reply as `:ok`, or as `:fail`/`:info` if the node answered with an
+/// `error`. Messages between nodes are ignored.
+///
+/// Operations can also be recorded by hand with [`HistoryRecorder::invoke`] and
+/// [`HistoryRecorder::complete`].
+pub struct HistoryRecorder {
+    start: Instant,
+    operations: Vec<Operation>,
+    processes: HashMap<NodeId, usize>,
+    /// (client, msg_id) -> (process, f, invocation value) of operations waiting for a reply
+    pending: HashMap<(NodeId, usize), (usize, String, Edn)>,
+}
+
+impl HistoryRecorder {
+    /// Start a new, empty history. Times are measured from this call.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            operations: Vec::new(),
+            processes: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Recorded operations, in order
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Record a message, timestamped with the time elapsed since the creation of the recorder
+    pub fn record(&mut self, msg: &Message<Value>) {
+        let time = self.start.elapsed().as_nanos() as u64;
+        self.record_at(msg, time);
+    }
+
+    /// Record a message that was observed at the given time, in nanoseconds
+    pub fn record_at(&mut self, msg: &Message<Value>, time: u64) {
+        if msg.src.kind() == NodeKind::Client {
+            if let Some(msg_id) = msg.body.msg_id {
+                let f = payload_type(&msg.body.payload).to_string();
+                let value = payload_value(&msg.body.payload);
+                let process = self.process(&msg.src);
+                self.push(OpType::Invoke, f.clone(), value.clone(), process, time);
+                self.pending
+                    .insert((msg.src.clone(), msg_id), (process, f, value));
+            }
+        } else if msg.dst.kind() == NodeKind::Client {
+            let Some(in_reply_to) = msg.body.in_reply_to else {
+                return;
+            };
+            let Some((process, f, invoked)) = self.pending.remove(&(msg.dst.clone(), in_reply_to))
+            else {
+                return;
+            };
+            let payload = &msg.body.payload;
+            let (op_type, value) = if payload_type(payload) == "error" {
+                let code = payload.get("code").and_then(Value::as_i64);
+                match code {
+                    Some(code) if INDEFINITE_ERROR_CODES.contains(&code) => (OpType::Info, invoked),
+                    _ => (OpType::Fail, invoked),
+                }
+            } else {
+                match payload_value(payload) {
+                    // acknowledgements without content complete with the invoked value
+                    Edn::Nil => (OpType::Ok, invoked),
+                    value => (OpType::Ok, value),
+                }
+            };
+            self.push(op_type, f, value, process, time);
+        }
+    }
+
+    /// Record the invocation of `f` by the given process
+    pub fn invoke(&mut self, process: usize, f: &str, value: Edn, time: u64) {
+        self.push(OpType::Invoke, f.to_string(), value, process, time);
+    }
+
+    /// Record the completion of an operation previously invoked by the given process
+    pub fn complete(&mut self, process: usize, op_type: OpType, f: &str, value: Edn, time: u64) {
+        self.push(op_type, f.to_string(), value, process, time);
+    }
+
+    /// Write the history, one operation per line, like Jepsen's `history.edn`
+    pub fn write_edn<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        for op in &self.operations {
+            writeln!(writer, "{op}")?;
+        }
+        writer.flush()
+    }
+
+    fn push(&mut self, op_type: OpType, f: String, value: Edn, process: usize, time: u64) {
+        let index = self.operations.len();
+        self.operations.push(Operation {
+            op_type,
+            f,
+            value,
+            process,
+            time,
+            index,
+        });
+    }
+
+    /// Give each client a process number, in order of appearance
+    fn process(&mut self, client: &NodeId) -> usize {
+        let next = self.processes.len();
+        *self.processes.entry(client.clone()).or_insert(next)
+    }
+}
+
+impl Default for HistoryRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn payload_type(payload: &Value) -> &str {
+    payload
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+}
+
+/// The value of an operation is the payload without its `type`: `nil` if there is nothing left,
+/// the field itself if there is only one (like Maelstrom does), or a map of all fields otherwise.
+fn payload_value(payload: &Value) -> Edn {
+    let Value::Object(map) = payload else {
+        return Edn::from(payload);
+    };
+    let mut fields = map.iter().filter(|(k, _)| k.as_str() != "type");
+    match (fields.next(), fields.next()) {
+        (None, _) => Edn::Nil,
+        (Some((_, value)), None) => Edn::from(value),
+        _ => Edn::Map(
+            map.iter()
+                .filter(|(k, _)| k.as_str() != "type")
+                .map(|(k, v)| (Edn::keyword(k), Edn::from(v)))
+                .collect(),
+        ),
+    }
+}
@@ -0,0 +1,213 @@
+//! Initialization of a node: receiving the `init` message and acknowledging it.
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, StdinLock, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metrics,
+    stdio::ProtocolStdout,
+    topology::{Strategy, Topology, TopologyHook},
+    trace,
+    transport::Transport,
+    Body, DynMessage, InputInterface, Message, NodeId, NodeMetadata, OutputInterface, NODE_ID,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum InitPayload {
+    Init {
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+    },
+    InitOk,
+}
+
+/// Helper to initialize a node with more control than [`Maelstrom::init`](crate::Maelstrom::init).
+///
+/// It is obtained with [`Maelstrom::builder`](crate::Maelstrom::builder) to communicate through
+/// stdin and stdout, or with [`InitBuilder::new`] to use other interfaces. Besides initializing the
+/// node with [`InitBuilder::init`], it can run a setup hook before the `init` message is
+/// acknowledged, so the node only reports being ready once its state is built:
+///
+/// ```
+/// use std::{collections::HashMap, io::Cursor};
+/// use node_driver::{InitBuilder, InputInterface, OutputInterface};
+///
+/// let init = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n0","n1","n2"]}}"#;
+/// let (metadata, state, _, _) = InitBuilder::new(
+///     InputInterface::new(Cursor::new(init)),
+///     OutputInterface::new(Vec::new()),
+/// )
+/// .init_with_state(|metadata| {
+///     // e.g. give each node its own slice of an id space
+///     Ok(HashMap::from([("first_id", metadata.node_index() * 1000)]))
+/// })
+/// .unwrap();
+///
+/// assert_eq!(metadata.node_ids, vec!["n0", "n1", "n2"]);
+/// assert_eq!(metadata.cluster_size(), 3);
+/// assert_eq!(state["first_id"], 1000);
+/// ```
+#[derive(Debug)]
+pub struct InitBuilder<R = StdinLock<'static>, W = ProtocolStdout> {
+    input: InputInterface<R>,
+    output: OutputInterface<W>,
+    topology: Option<TopologyHook>,
+}
+
+impl<R, W> InitBuilder<R, W>
+where
+    R: BufRead,
+    W: Write,
+{
+    /// Create a builder initializing a node communicating through the given interfaces
+    pub fn new(input: InputInterface<R>, output: OutputInterface<W>) -> Self {
+        Self {
+            input,
+            output,
+            topology: None,
+        }
+    }
+
+    /// Create a builder initializing a node communicating through the given [`Transport`].
+    ///
+    /// ```no_run
+    /// use node_driver::{transport::TcpTransport, InitBuilder};
+    ///
+    /// // run the node as part of a local TCP cluster configured by environment variables
+    /// let (metadata, input, output) = InitBuilder::from_transport(TcpTransport::from_env()?)?.init()?;
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn from_transport<T>(transport: T) -> anyhow::Result<Self>
+    where
+        T: Transport<Reader = R, Writer = W>,
+    {
+        let (reader, writer) = transport.split().context("Starting transport")?;
+        Ok(Self::new(
+            InputInterface::new(reader),
+            OutputInterface::new(writer),
+        ))
+    }
+
+    /// Initialize the node, see [`Maelstrom::init`](crate::Maelstrom::init)
+    pub fn init(self) -> anyhow::Result<(NodeMetadata, InputInterface<R>, OutputInterface<W>)> {
+        let (metadata, (), input, output) = self.init_with_state(|_| Ok(()))?;
+        Ok((metadata, input, output))
+    }
+
+    /// Initialize the node, running `setup` once the [`NodeMetadata`] is known but before the
+    /// `init` message is acknowledged, and return the state it built.
+    ///
+    /// If `setup` fails, the `init` message is not acknowledged and its error is returned.
+    pub fn init_with_state<S>(
+        self,
+        setup: impl FnOnce(&NodeMetadata) -> anyhow::Result<S>,
+    ) -> anyhow::Result<(NodeMetadata, S, InputInterface<R>, OutputInterface<W>)> {
+        let Self {
+            mut input,
+            output,
+            topology,
+        } = self;
+
+        // messages other than init may arrive first, keep them aside to deliver them later
+        let mut early_lines = VecDeque::new();
+        let init_msg = loop {
+            let Some(line) = input.next_line() else {
+                bail!(
+                    "Reached EOF before receiving the init message ({} other messages received)",
+                    early_lines.len()
+                );
+            };
+            let line = line.context("While reading the init message")?;
+            let msg: DynMessage = serde_json::from_str(&line)
+                .with_context(|| format!("Malformed message while waiting for init: {line}"))?;
+            match msg.payload_type() {
+                Some("init") => {
+                    trace::record_received(&line);
+                    drop(metrics::record_received(&line));
+                    break serde_json::from_str::<Message<InitPayload>>(&line)
+                        .with_context(|| format!("Malformed init message: {line}"))?;
+                }
+                Some("init_ok") => bail!("Nodes should never receive an init_ok message: {line}"),
+                _ => {
+                    tracing::warn!(msg = %line, "received a message before init, delivering it later");
+                    early_lines.push_back(line);
+                }
+            }
+        };
+        early_lines.append(&mut input.buffered);
+        input.buffered = early_lines;
+
+        let InitPayload::Init { node_id, node_ids } = init_msg.body.payload else {
+            unreachable!("the message type has been checked to be init");
+        };
+        let metadata = NodeMetadata::with_cluster(node_id.clone(), node_ids, 1)?;
+        let mut output = output.with_msg_ids(metadata.msg_id_allocator());
+        if let Some(topology) = topology {
+            input.topology = Some(topology.into_handler(
+                metadata.topology.clone(),
+                metadata.msg_id_allocator(),
+                &metadata.node_ids,
+            ));
+        }
+        let _ = NODE_ID.set(node_id);
+        tracing::info!(cluster = ?metadata.node_ids, "node initialized");
+
+        let state = setup(&metadata).context("While setting up the node")?;
+
+        // only acknowledge once the node is ready to handle messages
+        output
+            .send_msg(Message {
+                src: init_msg.dst,
+                dst: init_msg.src,
+                body: Body {
+                    msg_id: Some(0),
+                    in_reply_to: init_msg.body.msg_id,
+                    ..Body::new(InitPayload::InitOk)
+                },
+            })
+            .context("While responding to init message")?;
+
+        Ok((metadata, state, input, output))
+    }
+}
+
+impl<R, W> InitBuilder<R, W>
+where
+    W: Write + Send + 'static,
+{
+    /// Let the node handle `topology` messages by itself: the [`InputInterface`] acknowledges them
+    /// and stores their [`Topology`] in the [`NodeMetadata`] instead of yielding them, then calls
+    /// `hook` with the new topology. See [`topology`](crate::topology).
+    pub fn handle_topology(mut self, hook: impl FnMut(&Topology) + Send + 'static) -> Self {
+        let writer: Arc<Mutex<dyn Write + Send>> = self.output.writer.clone();
+        let strategy = self.topology.as_ref().map(|hook| hook.strategy);
+        let mut hook = TopologyHook::new(Arc::downgrade(&writer), Box::new(hook));
+        hook.strategy = strategy.unwrap_or_default();
+        self.topology = Some(hook);
+        self
+    }
+
+    /// Compute the topology of the node from the ids of the cluster with the given [`Strategy`],
+    /// instead of using the one suggested by Maelstrom, which is only acknowledged.
+    ///
+    /// This implies [`InitBuilder::handle_topology`], whose hook is called once with the
+    /// computed topology during initialization. [`Strategy::Maelstrom`] keeps the suggested
+    /// topology.
+    pub fn topology_strategy(mut self, strategy: Strategy) -> Self {
+        if self.topology.is_none() {
+            self = self.handle_topology(|_| {});
+        }
+        if let Some(hook) = &mut self.topology {
+            hook.strategy = strategy;
+        }
+        self
+    }
+}
@@ -0,0 +1,278 @@
+//! A compact set of integers, stored and serialized as ranges of consecutive values.
+//!
+//! Sets of ids or broadcast values tend to be made of long runs of consecutive integers, which a
+//! `HashSet<usize>` serializes as a json array growing with every value. An [`IntSet`] instead
+//! stores each run as a single range, and serializes it as an array where a lone value is a
+//! number and a run is a `[first, last]` pair:
+//!
+//! ```
+//! use node_driver::intset::IntSet;
+//!
+//! let set: IntSet = [1, 2, 3, 4, 5, 8, 10, 11].into_iter().collect();
+//! assert_eq!(serde_json::to_string(&set).unwrap(), "[[1,5],8,[10,11]]");
+//! assert_eq!(set.len(), 8);
+//!
+//! // a plain array of integers is also understood
+//! let parsed: IntSet = serde_json::from_str("[4,1,2,3]").unwrap();
+//! assert_eq!(serde_json::to_string(&parsed).unwrap(), "[[1,4]]");
+//!
+//! // the number of values must fit in a usize
+//! assert!(serde_json::from_str::<IntSet>(&format!("[[0,{}]]", usize::MAX)).is_err());
+//! assert!(serde_json::from_str::<IntSet>(&format!("[[1,{}],0]", usize::MAX)).is_err());
+//! ```
+
+use std::{
+    collections::{btree_map, BTreeMap},
+    fmt,
+    iter::FlatMap,
+    ops::{Bound, RangeInclusive},
+};
+
+use serde::{de::Error as _, ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A set of integers stored as ranges of consecutive values, see the [module docs](self).
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct IntSet {
+    /// the last value of each range, by first value. Ranges never overlap nor touch.
+    ranges: BTreeMap<usize, usize>,
+    len: usize,
+}
+
+impl IntSet {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of values in the set
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the set has no value
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the set contains the value
+    pub fn contains(&self, value: usize) -> bool {
+        self.ranges
+            .range(..=value)
+            .next_back()
+            .is_some_and(|(_, &last)| last >= value)
+    }
+
+    /// Add a value, returning whether it was not in the set yet
+    pub fn insert(&mut self, value: usize) -> bool {
+        self.insert_range(value..=value) > 0
+    }
+
+    /// Add a range of values, returning the number of values which were not in the set yet
+    ///
+    /// ```
+    /// use node_driver::intset::IntSet;
+    ///
+    /// let mut set = IntSet::new();
+    /// assert_eq!(set.insert_range(3..=6), 4);
+    /// assert_eq!(set.insert_range(1..=4), 2);
+    /// assert_eq!(set.ranges().collect::<Vec<_>>(), [1..=6]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values of the set would not fit in a `usize`, which only happens
+    /// when it would hold every `usize`.
+    pub fn insert_range(&mut self, range: RangeInclusive<usize>) -> usize {
+        self.checked_insert_range(range)
+            .expect("the number of values of the set should fit in a usize")
+    }
+
+    /// Add a range of values like [`insert_range`](Self::insert_range), or return `None` and leave
+    /// the set unchanged if its number of values would not fit in a `usize`
+    fn checked_insert_range(&mut self, range: RangeInclusive<usize>) -> Option<usize> {
+        let (mut first, mut last) = range.into_inner();
+        if first > last {
+            return Some(0);
+        }
+        // merge the ranges overlapping or touching the new one
+        let merged: Vec<(usize, usize)> = self
+            .ranges
+            .range(..=last.saturating_add(1))
+            .rev()
+            .take_while(|(_, &end)| end.saturating_add(1) >= first)
+            .map(|(&start, &end)| (start, end))
+            .collect();
+        let mut len = self.len;
+        for &(start, end) in &merged {
+            len -= end - start + 1;
+            first = first.min(start);
+            last = last.max(end);
+        }
+        let len = len.checked_add((last - first).checked_add(1)?)?;
+        for (start, _) in merged {
+            self.ranges.remove(&start);
+        }
+        self.ranges.insert(first, last);
+        let added = len - self.len;
+        self.len = len;
+        Some(added)
+    }
+
+    /// Add every value of another set
+    pub fn union_with(&mut self, other: &IntSet) {
+        for range in other.ranges() {
+            self.insert_range(range);
+        }
+    }
+
+    /// The values of this set which are not in `other`
+    ///
+    /// ```
+    /// use node_driver::intset::IntSet;
+    ///
+    /// let set: IntSet = (1..=10).collect();
+    /// let other: IntSet = [0, 3, 4, 9].into_iter().collect();
+    /// assert_eq!(set.difference(&other).ranges().collect::<Vec<_>>(), [1..=2, 5..=8, 10..=10]);
+    /// ```
+    pub fn difference(&self, other: &IntSet) -> IntSet {
+        let mut difference = IntSet::new();
+        for (&first, &last) in &self.ranges {
+            // the ranges of `other` overlapping this one, in order
+            let overlapping = other
+                .ranges
+                .range(..=first)
+                .next_back()
+                .filter(|(_, &end)| end >= first)
+                .into_iter()
+                .chain(
+                    other
+                        .ranges
+                        .range((Bound::Excluded(first), Bound::Included(last))),
+                );
+            // the first value of the range not known to be in `other` yet
+            let mut next = Some(first);
+            for (&start, &end) in overlapping {
+                let Some(current) = next else { break };
+                if start > current {
+                    difference.insert_range(current..=start - 1);
+                }
+                next = end.checked_add(1).filter(|&next| next <= last);
+            }
+            if let Some(current) = next {
+                difference.insert_range(current..=last);
+            }
+        }
+        difference
+    }
+
+    /// The ranges of consecutive values of the set, in increasing order
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<usize>> + '_ {
+        self.ranges.iter().map(|(&first, &last)| first..=last)
+    }
+
+    /// The values of the set, in increasing order
+    pub fn iter(&self) -> Iter<'_> {
+        self.into_iter()
+    }
+}
+
+/// The values of an [`IntSet`], in increasing order
+pub type Iter<'a> = FlatMap<
+    btree_map::Iter<'a, usize, usize>,
+    RangeInclusive<usize>,
+    fn((&usize, &usize)) -> RangeInclusive<usize>,
+>;
+
+/// The values of an [`IntSet`], in increasing order
+pub type IntoIter = FlatMap<
+    btree_map::IntoIter<usize, usize>,
+    RangeInclusive<usize>,
+    fn((usize, usize)) -> RangeInclusive<usize>,
+>;
+
+impl<'a> IntoIterator for &'a IntSet {
+    type Item = usize;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges.iter().flat_map(|(&first, &last)| first..=last)
+    }
+}
+
+impl IntoIterator for IntSet {
+    type Item = usize;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges
+            .into_iter()
+            .flat_map(|(first, last)| first..=last)
+    }
+}
+
+impl FromIterator<usize> for IntSet {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut set = IntSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<usize> for IntSet {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, iter: I) {
+        for value in iter {
+            self.insert(value);
+        }
+    }
+}
+
+impl fmt::Debug for IntSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.ranges()).finish()
+    }
+}
+
+/// A lone value or a range of values, as serialized in an [`IntSet`]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Element {
+    Value(usize),
+    Range(usize, usize),
+}
+
+impl Serialize for IntSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.ranges.len()))?;
+        for (first, last) in &self.ranges {
+            if first == last {
+                seq.serialize_element(first)?;
+            } else {
+                seq.serialize_element(&[first, last])?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for IntSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = IntSet::new();
+        for element in Vec::<Element>::deserialize(deserializer)? {
+            let range = match element {
+                Element::Value(value) => value..=value,
+                Element::Range(first, last) if first <= last => first..=last,
+                Element::Range(first, last) => {
+                    return Err(D::Error::custom(format!(
+                        "Invalid range [{first}, {last}] in a set of integers"
+                    )))
+                }
+            };
+            if set.checked_insert_range(range).is_none() {
+                return Err(D::Error::custom(
+                    "Too many values in a set of integers, their number must fit in a usize",
+                ));
+            }
+        }
+        Ok(set)
+    }
+}
@@ -4,10 +4,58 @@
 //! abstracting away the usage of the stdin and stdout and the json conversions.
 //!
 
-use std::io::{BufRead, Read, StdinLock, StdoutLock, Write};
+pub mod anti_entropy;
+pub mod bloom;
+pub mod diagram;
+pub mod dispatch;
+mod dynamic;
+pub mod gossip;
+pub mod history;
+mod init;
+pub mod intset;
+pub mod logging;
+pub mod metrics;
+mod node_id;
+mod payload;
+pub mod reliable;
+pub mod round;
+pub mod rpc;
+pub mod sender;
+pub mod stdio;
+pub mod topology;
+pub mod trace;
+pub mod transport;
 
-use anyhow::Context;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, StdinLock, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{
+    de::{DeserializeOwned, Error as _},
+    Deserialize, Deserializer, Serialize,
+};
+use serde_json::{Map, Value};
+use stdio::ProtocolStdout;
+use topology::{SharedTopology, Topology, TopologyHandler};
+
+pub use dynamic::{DynMessage, Envelope, EnvelopeBody};
+pub use init::InitBuilder;
+pub use node_driver_derive::MaelstromPayload;
+pub use node_id::{NodeId, NodeKind};
+pub use payload::{MaelstromPayload, Request};
+pub use sender::{MessageSender, WriterHandle};
+
+/// Items used by the code generated by the derive macros, not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
 
 /// A message that you can send within the Maelstrom network.
 ///
@@ -16,7 +64,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 ///
 /// ```
 /// use serde::{Serialize, Deserialize};
-/// use node_driver::{Message, Body};
+/// use node_driver::{Message, Body, NodeId};
 ///
 /// // define a custom enum for our payload
 /// #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,23 +76,23 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 /// }
 /// // create a message with a payload `MyPayload::V1`
 /// let message = Message {
-///     src: String::from("src"),
-///     dst: String::from("dst"),
+///     src: NodeId::from("c1"),
+///     dst: NodeId::from("n1"),
 ///     body: Body {
 ///         msg_id: Some(1234),
-///         in_reply_to: None,
-///         payload: MyPayload::V1,
+///         ..Body::new(MyPayload::V1)
 ///     }
 /// };
 /// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message<P> {
     /// The identifier of the Maelstrom node that send the message
-    pub src: String,
+    pub src: NodeId,
     /// The identifier of the recipient Maelstrom node
     #[serde(rename = "dest")]
-    pub dst: String,
+    pub dst: NodeId,
     /// The body of the message, generic over the type of payload
+    #[serde(bound(deserialize = "P: Serialize + DeserializeOwned"))]
     pub body: Body<P>,
 }
 
@@ -60,7 +108,7 @@ impl<P> Message<P> {
             body: Body {
                 msg_id,
                 in_reply_to: self.body.msg_id,
-                payload,
+                ..Body::new(payload)
             },
         }
     }
@@ -70,7 +118,39 @@ impl<P> Message<P> {
 ///
 /// This defines the optional fields specified in [the protocol](https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md) but the `type` field
 /// is expected to be provided by the Payload type, which is flattened into the message.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// The `extra` fields are flattened into the body too, next to the payload. This lets nodes attach
+/// metadata to their messages, like a trace id, without changing every payload type. When a body
+/// is deserialized, the fields which the payload does not serialize back end up in `extra`, so
+/// they survive a round trip.
+///
+/// ```
+/// use node_driver::{Body, DynMessage, Message};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Debug, Serialize, Deserialize)]
+/// #[serde(tag = "type", rename_all = "snake_case")]
+/// enum Payload {
+///     Echo { echo: String },
+/// }
+///
+/// let line = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi","trace_id":"abc"}}"#;
+/// let msg: Message<Payload> = serde_json::from_str(line).unwrap();
+/// assert_eq!(msg.body.extra["trace_id"], "abc");
+/// assert!(serde_json::to_string(&msg).unwrap().ends_with(r#""echo":"hi","trace_id":"abc"}}"#));
+///
+/// let msg: DynMessage = serde_json::from_str(line).unwrap();
+/// let msg: Message<Payload> = msg.into_typed().unwrap();
+/// assert_eq!(msg.body.extra["trace_id"], "abc");
+///
+/// let mut body = Body::new(Payload::Echo { echo: "hi".into() });
+/// body.extra.insert("trace_id".into(), "def".into());
+/// assert_eq!(
+///     serde_json::to_string(&body).unwrap(),
+///     r#"{"msg_id":null,"in_reply_to":null,"type":"echo","echo":"hi","trace_id":"def"}"#
+/// );
+/// ```
+#[derive(Debug, Clone, Serialize)]
 pub struct Body<P> {
     /// An optional id for the message
     pub msg_id: Option<usize>,
@@ -80,84 +160,346 @@ pub struct Body<P> {
     /// according to [the protocol](https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md)
     #[serde(flatten)]
     pub payload: P,
+    /// Fields of the body which are not part of the payload, flattened into the body. Their names
+    /// should not clash with the fields of the payload.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-enum InitPayload {
-    Init {
-        node_id: String,
-        node_ids: Vec<String>,
-    },
-    InitOk,
+impl<P> Body<P> {
+    /// Create a body holding the given payload, without ids nor extra fields
+    pub fn new(payload: P) -> Self {
+        Self {
+            msg_id: None,
+            in_reply_to: None,
+            payload,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl<'de, P> Deserialize<'de> for Body<P>
+where
+    P: Serialize + DeserializeOwned,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut fields = Map::deserialize(deserializer)?;
+        let mut header = |name| {
+            fields
+                .remove(name)
+                .map(serde_json::from_value)
+                .transpose()
+                .map(Option::flatten)
+                .map_err(D::Error::custom)
+        };
+        let msg_id = header("msg_id")?;
+        let in_reply_to = header("in_reply_to")?;
+        let (payload, extra) = split_extra(Value::Object(fields)).map_err(D::Error::custom)?;
+        Ok(Self {
+            msg_id,
+            in_reply_to,
+            payload,
+            extra,
+        })
+    }
+}
+
+/// Deserialize a payload from the fields of a body, and return the fields it ignored.
+///
+/// serde does not tell which fields were used, so the payload is serialized back: the fields
+/// missing from the result are the ignored ones.
+fn split_extra<P>(fields: Value) -> serde_json::Result<(P, Map<String, Value>)>
+where
+    P: Serialize + DeserializeOwned,
+{
+    let payload = P::deserialize(&fields)?;
+    let Value::Object(fields) = fields else {
+        return Ok((payload, Map::new()));
+    };
+    let used = serde_json::to_value(&payload)?;
+    let extra = fields
+        .into_iter()
+        .filter(|(name, _)| used.get(name).is_none())
+        .collect();
+    Ok((payload, extra))
+}
+
+static NODE_ID: OnceLock<NodeId> = OnceLock::new();
+
+/// Id of the current node, once it has been initialized
+pub(crate) fn node_id() -> Option<&'static str> {
+    NODE_ID.get().map(NodeId::as_str)
 }
 
 fn parse_msg<P>(msg: &str) -> anyhow::Result<Message<P>>
 where
-    P: DeserializeOwned,
+    P: Serialize + DeserializeOwned,
 {
     serde_json::from_str(msg).context("Message cannot be deserialized.")
 }
 
 /// An interface to handle receiving [`Message`] from the Maelstrom network
 ///
-/// This handles transparently the json deserialization and the reading from stdin
-pub struct InputInterface {
-    stdin: StdinLock<'static>,
+/// This handles transparently the json deserialization and the reading from stdin.
+///
+/// Messages are read from stdin by default, but any [`BufRead`] source of json lines can be used
+/// instead, which comes in handy to test a node:
+///
+/// ```
+/// use std::io::Cursor;
+/// use node_driver::InputInterface;
+///
+/// let lines = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi"}}"#;
+/// let mut input = InputInterface::new(Cursor::new(lines));
+/// let msg = input.iter::<serde_json::Value>().next().unwrap().unwrap();
+/// assert_eq!(msg.body.payload["echo"], "hi");
+/// ```
+#[derive(Debug)]
+pub struct InputInterface<R = StdinLock<'static>> {
+    reader: R,
+    /// lines read ahead of time, to deliver before reading new ones
+    buffered: VecDeque<String>,
+    /// handles `topology` messages instead of the node, see [`topology`]
+    topology: Option<TopologyHandler>,
 }
 
-impl InputInterface {
+impl<R: BufRead> InputInterface<R> {
+    /// Create an interface reading json lines from the given reader
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffered: VecDeque::new(),
+            topology: None,
+        }
+    }
+
+    /// Consume the interface, returning the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read from another reader, keeping the messages buffered by this interface and its handling
+    /// of `topology` messages.
+    ///
+    /// The default interface locks stdin, and cannot be sent to another thread. Reading stdin
+    /// through a [`BufReader`](std::io::BufReader) instead gives an interface that can:
+    ///
+    /// ```no_run
+    /// use std::io::BufReader;
+    /// use node_driver::Maelstrom;
+    ///
+    /// let (metadata, input, output) = Maelstrom::init()?;
+    /// let mut input = input.with_reader(BufReader::new(std::io::stdin()));
+    /// std::thread::spawn(move || {
+    ///     for msg in input.iter::<serde_json::Value>() {
+    ///         // ...
+    ///     }
+    /// });
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn with_reader<R2: BufRead>(self, reader: R2) -> InputInterface<R2> {
+        InputInterface {
+            reader,
+            buffered: self.buffered,
+            topology: self.topology,
+        }
+    }
+
     /// Obtain an interator over messages of type [`Message<P>`].
     ///
     /// The iterator items are [`anyhow::Result`] containing [`Message<P>`] since reading from stdin and parsing messages is a failible operation.
+    ///
+    /// Once stdin reaches EOF, the [`metrics`] of the node are dumped. Messages are also appended
+    /// to a [`trace`] if one is configured.
+    ///
+    /// If the node was initialized with
+    /// [`InitBuilder::handle_topology`], `topology` messages are handled without being yielded.
     pub fn iter<P>(&mut self) -> impl Iterator<Item = anyhow::Result<Message<P>>> + '_
     where
-        P: DeserializeOwned,
+        P: Serialize + DeserializeOwned,
     {
-        self.stdin
-            .by_ref()
-            .lines()
-            .map(|line_result| parse_msg(&line_result.context("Reading from stdin")?))
+        let mut handler_timer = None;
+        std::iter::from_fn(move || loop {
+            // the previous message has been handled once the next one is requested
+            drop(handler_timer.take());
+            let Some(line_result) = self.next_line() else {
+                if let Err(e) = metrics::dump() {
+                    tracing::warn!(error = %e, "could not dump metrics");
+                }
+                return None;
+            };
+            let line = match line_result.context("Reading from stdin") {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            trace::record_received(&line);
+            handler_timer = metrics::record_received(&line);
+            if let Some(handler) = &mut self.topology {
+                if topology::is_topology(&line) {
+                    if let Err(e) = handler.handle(&line) {
+                        return Some(Err(e));
+                    }
+                    continue;
+                }
+            }
+            return Some(parse_msg(&line));
+        })
+    }
+
+    /// Read the next line, starting with the buffered ones
+    fn next_line(&mut self) -> Option<std::io::Result<String>> {
+        if let Some(line) = self.buffered.pop_front() {
+            return Some(Ok(line));
+        }
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                let len = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(len);
+                tracing::debug!(msg = %line, "received");
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e)),
+        }
     }
 }
 
 impl Default for InputInterface {
     fn default() -> Self {
-        Self {
-            stdin: std::io::stdin().lock(),
-        }
+        Self::new(std::io::stdin().lock())
     }
 }
 
 /// An interface to handle sending `Message` to the Maelstrom network
 ///
-/// This handles transparently the json serialization and the writing to stdout
-pub struct OutputInterface {
-    stdout: StdoutLock<'static>,
+/// This handles transparently the json serialization and the writing to stdout. Messages are
+/// written to the original stdout even after it has been redirected to stderr, see [`stdio`].
+///
+/// Any other [`Write`] destination can be used instead, for instance to inspect what a node sends:
+///
+/// ```
+/// use node_driver::{Body, Message, OutputInterface};
+///
+/// let mut output = OutputInterface::new(Vec::new());
+/// output.send_msg(Message {
+///     src: "n1".into(),
+///     dst: "c1".into(),
+///     body: Body { msg_id: Some(1), ..Body::new(serde_json::json!({"type": "read"})) },
+/// }).unwrap();
+/// assert_eq!(
+///     String::from_utf8(output.into_inner().unwrap()).unwrap(),
+///     "{\"src\":\"n1\",\"dest\":\"c1\",\"body\":{\"msg_id\":1,\"in_reply_to\":null,\"type\":\"read\"}}\n"
+/// );
+/// ```
+#[derive(Debug)]
+pub struct OutputInterface<W = ProtocolStdout> {
+    /// shared with the input interface when it handles `topology` messages
+    writer: Arc<Mutex<W>>,
+    msg_ids: Option<MsgIdAllocator>,
 }
 
-impl OutputInterface {
+impl<W: Write> OutputInterface<W> {
+    /// Create an interface writing json lines to the given writer
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(writer)),
+            msg_ids: None,
+        }
+    }
+
+    /// Number the messages sent without a `msg_id` with ids taken from the given allocator.
+    ///
+    /// [`Maelstrom::init`] sets this up with the allocator of the [`NodeMetadata`] it returns.
+    pub fn with_msg_ids(mut self, msg_ids: MsgIdAllocator) -> Self {
+        self.msg_ids = Some(msg_ids);
+        self
+    }
+
+    /// Consume the interface, returning the underlying writer.
+    ///
+    /// This fails while the input interface is using the writer to acknowledge a `topology`
+    /// message.
+    pub fn into_inner(self) -> anyhow::Result<W> {
+        // others only hold weak references to the writer, and only upgrade them to write a message
+        let writer = Arc::into_inner(self.writer)
+            .ok_or_else(|| anyhow!("The writer of the output interface is in use"))?;
+        Ok(writer.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+
     /// Send a [`Message<P>`] to the malestrom Network
     ///
+    /// If the message has no `msg_id` and this interface has a [`MsgIdAllocator`], a new id is
+    /// given to the message.
+    ///
     /// This returns a [`anyhow::Result`] since writing to stdout if a failible operation.
-    pub fn send_msg<P>(&mut self, msg: Message<P>) -> anyhow::Result<()>
+    pub fn send_msg<P>(&mut self, mut msg: Message<P>) -> anyhow::Result<()>
+    where
+        P: Serialize,
+    {
+        if let Some(msg_ids) = &self.msg_ids {
+            msg.body.msg_id.get_or_insert_with(|| msg_ids.next_id());
+        }
+        let line = serde_json::to_string(&msg).context("Serializing message")?;
+        self.write_line(line)
+    }
+
+    /// Send a [`Message<P>`] which expects no reply, without giving it a `msg_id`
+    ///
+    /// Unlike messages sent with [`send_msg`](Self::send_msg), it does not count as a request
+    /// waiting for a reply in the [`metrics`].
+    ///
+    /// ```
+    /// use node_driver::{Body, Message, MsgIdAllocator, OutputInterface};
+    ///
+    /// let mut output = OutputInterface::new(Vec::new()).with_msg_ids(MsgIdAllocator::new(1));
+    /// output
+    ///     .send_oneway(Message {
+    ///         src: "n1".into(),
+    ///         dst: "n2".into(),
+    ///         body: Body::new(serde_json::json!({"type": "gossip"})),
+    ///     })
+    ///     .unwrap();
+    /// let output = String::from_utf8(output.into_inner().unwrap()).unwrap();
+    /// assert!(output.contains(r#""msg_id":null"#));
+    /// ```
+    pub fn send_oneway<P>(&mut self, msg: Message<P>) -> anyhow::Result<()>
     where
         P: Serialize,
     {
-        serde_json::to_writer(&mut self.stdout, &msg).context("Serializing message")?;
-        self.stdout
-            .write_all(b"\n")
-            .context("Writing trailing newline")?;
-        Ok(())
+        let line = serde_json::to_string(&msg).context("Serializing message")?;
+        self.write_line(line)
+    }
+
+    /// Write a serialized message
+    fn write_line(&mut self, line: String) -> anyhow::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        write_line(&mut *writer, line)
     }
 }
 
+/// Write a serialized message and its newline to the network
+fn write_line(writer: &mut dyn Write, mut line: String) -> anyhow::Result<()> {
+    tracing::debug!(msg = %line, "sent");
+    metrics::record_sent(&line);
+    trace::record_sent(&line);
+    // write the message and its newline with a single call: the writer is locked for the whole call,
+    // either by the output interface or by the process-wide lock of ProtocolStdout, so lines from
+    // several threads don't mix
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .context("Writing message")?;
+    writer.flush().context("Flushing message")?;
+    Ok(())
+}
+
 impl Default for OutputInterface {
     fn default() -> Self {
-        Self {
-            stdout: std::io::stdout().lock(),
-        }
+        Self::new(ProtocolStdout::get())
     }
 }
 
@@ -169,69 +511,206 @@ impl Maelstrom {
     ///
     /// This handles receiving the `Init` message and responding to it, and returns a [`NodeMetadata`] instance holding informations about the Maelstrom node,
     /// as well as an [`InputInterface`] and an [`OutputInterface`] to communicate with Maelstrom.
-    /// This is a failible operation since it communicates with the Maelstrom clients.
+    /// The output interface gives ids from the [`NodeMetadata`] to messages sent without `msg_id`.
+    /// This is a failible operation since it communicates with the Maelstrom clients: it fails if
+    /// stdin reaches EOF or contains malformed messages before the `Init` message arrives.
+    ///
+    /// This also sets up [`logging`] to stderr.
     pub fn init() -> anyhow::Result<(NodeMetadata, InputInterface, OutputInterface)> {
-        let mut input = InputInterface::default();
-        let init_msg: Message<InitPayload> = input
-            .iter()
-            .next()
-            .expect("Nothing to read from stdin")
-            .context("While getting init message")?;
-
-        let mut output = OutputInterface::default();
-        output
-            .send_msg(Message {
-                src: init_msg.dst,
-                dst: init_msg.src,
-                body: Body {
-                    payload: InitPayload::InitOk,
-                    msg_id: Some(0),
-                    in_reply_to: init_msg.body.msg_id,
-                },
-            })
-            .context("While repsonding to init message")?;
-
-        match init_msg.body.payload {
-            InitPayload::Init { node_id, node_ids } => Ok((
-                NodeMetadata::new(
-                    node_id.clone(),
-                    node_ids
-                        .iter()
-                        .filter(|&nid| *nid != node_id)
-                        .cloned()
-                        .collect::<Vec<String>>(),
-                    1,
-                ),
-                input,
-                output,
-            )),
-            InitPayload::InitOk => panic!("Node should never receive an InitOk message"),
-        }
+        Self::builder().init()
+    }
+
+    /// Initialize a Maelstrom node communicating through the given interfaces instead of stdin and
+    /// stdout.
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use node_driver::{InputInterface, Maelstrom, OutputInterface};
+    ///
+    /// let init = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2"]}}"#;
+    /// let (metadata, _, output) = Maelstrom::init_with(
+    ///     InputInterface::new(Cursor::new(init)),
+    ///     OutputInterface::new(Vec::new()),
+    /// ).unwrap();
+    /// assert_eq!(metadata.node_id, "n1");
+    /// assert_eq!(metadata.other_nodes_ids, vec!["n2"]);
+    /// assert!(String::from_utf8(output.into_inner().unwrap()).unwrap().contains(r#""type":"init_ok""#));
+    /// ```
+    ///
+    /// Messages received before `init` are delivered by the input interface once the node is
+    /// initialized, while reaching EOF, reading malformed json or receiving `init_ok` is an error:
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use node_driver::{InputInterface, Maelstrom, OutputInterface};
+    ///
+    /// let lines = r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":1}}
+    /// {"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+    /// let (_, mut input, _) = Maelstrom::init_with(
+    ///     InputInterface::new(Cursor::new(lines)),
+    ///     OutputInterface::new(Vec::new()),
+    /// ).unwrap();
+    /// let early = input.iter::<serde_json::Value>().next().unwrap().unwrap();
+    /// assert_eq!(early.body.payload["type"], "read");
+    ///
+    /// let eof = Maelstrom::init_with(
+    ///     InputInterface::new(Cursor::new("")),
+    ///     OutputInterface::new(Vec::new()),
+    /// );
+    /// assert!(eof.unwrap_err().to_string().contains("EOF"));
+    /// ```
+    pub fn init_with<R, W>(
+        input: InputInterface<R>,
+        output: OutputInterface<W>,
+    ) -> anyhow::Result<(NodeMetadata, InputInterface<R>, OutputInterface<W>)>
+    where
+        R: BufRead,
+        W: Write,
+    {
+        InitBuilder::new(input, output).init()
+    }
+
+    /// Obtain an [`InitBuilder`] communicating through stdin and stdout, to customize the
+    /// initialization of the node.
+    ///
+    /// This also sets up [`logging`] to stderr.
+    pub fn builder() -> InitBuilder {
+        logging::init();
+        InitBuilder::new(InputInterface::default(), OutputInterface::default())
     }
 }
 
 /// Holds metadata about the Maelstrom node
+#[derive(Debug)]
 pub struct NodeMetadata {
     /// Id of the current Maelstrom node
-    pub node_id: String,
+    pub node_id: NodeId,
     /// Ids of all the other nodes in the network
-    pub other_nodes_ids: Vec<String>,
-    next_message_id: usize,
+    pub other_nodes_ids: Vec<NodeId>,
+    /// Ids of all the nodes in the network, including this one, in the order given by Maelstrom
+    pub node_ids: Vec<NodeId>,
+    msg_ids: MsgIdAllocator,
+    topology: SharedTopology,
 }
 
 impl NodeMetadata {
     /// Instantiate a new NodeMetadata object
-    pub fn new(node_id: String, other_nodes_ids: Vec<String>, next_message_id: usize) -> Self {
+    ///
+    /// The cluster is made of this node followed by the other nodes, use
+    /// [`NodeMetadata::with_cluster`] to specify the order of the nodes.
+    pub fn new(node_id: NodeId, other_nodes_ids: Vec<NodeId>, next_message_id: usize) -> Self {
+        let node_ids = std::iter::once(node_id.clone())
+            .chain(other_nodes_ids.iter().cloned())
+            .collect();
         Self {
             node_id,
             other_nodes_ids,
-            next_message_id,
+            node_ids,
+            msg_ids: MsgIdAllocator::new(next_message_id),
+            topology: Default::default(),
+        }
+    }
+    /// Instantiate a new NodeMetadata object from the ordered ids of all the nodes of the cluster,
+    /// which must include `node_id`, or this fails.
+    ///
+    /// ```
+    /// use node_driver::{NodeId, NodeMetadata};
+    ///
+    /// let ids: Vec<NodeId> = vec!["n0".into(), "n1".into(), "n2".into()];
+    /// let metadata = NodeMetadata::with_cluster("n1".into(), ids, 1).unwrap();
+    /// assert_eq!(metadata.node_index(), 1);
+    /// assert_eq!(metadata.cluster_size(), 3);
+    /// assert_eq!(metadata.other_nodes_ids, vec!["n0", "n2"]);
+    ///
+    /// assert!(NodeMetadata::with_cluster("n3".into(), vec!["n0".into()], 1).is_err());
+    /// ```
+    pub fn with_cluster(
+        node_id: NodeId,
+        node_ids: Vec<NodeId>,
+        next_message_id: usize,
+    ) -> anyhow::Result<Self> {
+        if !node_ids.contains(&node_id) {
+            bail!("Node {node_id} is not part of the cluster {node_ids:?}");
+        }
+        let other_nodes_ids = node_ids
+            .iter()
+            .filter(|&nid| *nid != node_id)
+            .cloned()
+            .collect();
+        Ok(Self {
+            node_id,
+            other_nodes_ids,
+            node_ids,
+            msg_ids: MsgIdAllocator::new(next_message_id),
+            topology: Default::default(),
+        })
+    }
+    /// Position of this node in [`NodeMetadata::node_ids`], useful to give each node a distinct
+    /// role (e.g. to elect node 0 as a leader)
+    pub fn node_index(&self) -> usize {
+        self.node_ids
+            .iter()
+            .position(|nid| *nid == self.node_id)
+            .expect("node_ids should include node_id, as checked on creation")
+    }
+    /// Number of nodes in the cluster, including this one
+    pub fn cluster_size(&self) -> usize {
+        self.node_ids.len()
+    }
+    /// Obtain the next message id to use
+    pub fn get_next_msg_id(&self) -> usize {
+        self.msg_ids.next_id()
+    }
+    /// Obtain a handle to the allocator of message ids of this node, which can be shared with
+    /// other threads
+    pub fn msg_id_allocator(&self) -> MsgIdAllocator {
+        self.msg_ids.clone()
+    }
+    /// The last topology received, if the node handles `topology` messages with
+    /// [`InitBuilder::handle_topology`]
+    pub fn topology(&self) -> Option<Topology> {
+        self.topology
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+    /// The neighbours of this node in the last topology received, none until it arrives
+    pub fn neighbours(&self) -> Vec<NodeId> {
+        self.topology
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|topology| topology.neighbours_of(self.node_id.as_str()).to_vec())
+            .unwrap_or_default()
+    }
+}
+
+/// Allocates unique message ids, and can be shared between threads.
+///
+/// Clones share the same counter, so ids are never reused whichever thread allocates them.
+///
+/// ```
+/// use node_driver::MsgIdAllocator;
+///
+/// let ids = MsgIdAllocator::new(1);
+/// let other = ids.clone();
+/// assert_eq!(ids.next_id(), 1);
+/// assert_eq!(other.next_id(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MsgIdAllocator {
+    next: Arc<AtomicUsize>,
+}
+
+impl MsgIdAllocator {
+    /// Create an allocator whose first id is `first`
+    pub fn new(first: usize) -> Self {
+        Self {
+            next: Arc::new(AtomicUsize::new(first)),
         }
     }
     /// Obtain the next message id to use
-    pub fn get_next_msg_id(&mut self) -> usize {
-        let next_msg_id = self.next_message_id;
-        self.next_message_id += 1;
-        next_msg_id
+    pub fn next_id(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed)
     }
 }
@@ -0,0 +1,74 @@
+//! Logging to stderr, using the [`tracing`] ecosystem.
+//!
+//! Stdout is reserved for the Maelstrom protocol, and a stray `println!` produces an invalid
+//! message. Maelstrom however forwards everything written to stderr to the node logs, so this is
+//! where logs should go.
+//!
+//! [`Maelstrom::init`](crate::Maelstrom::init) installs a subscriber writing to stderr, so you
+//! only have to use the `tracing` macros in your node:
+//!
+//! ```no_run
+//! let (node_metadata, mut input, mut output) = node_driver::Maelstrom::init()?;
+//! tracing::info!(peers = node_metadata.other_nodes_ids.len(), "node started");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! Every event is prefixed with the id of the node. The verbosity is configured with the
+//! [`LOG_ENV_VAR`] environment variable, using the
+//! [`EnvFilter`](tracing_subscriber::EnvFilter) syntax (e.g. `NODE_DRIVER_LOG=debug`). At the
+//! `debug` level, every message sent or received by the node is logged.
+
+use std::fmt;
+
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    fmt::{
+        format::{self, Writer},
+        FmtContext, FormatEvent, FormatFields,
+    },
+    registry::LookupSpan,
+    EnvFilter,
+};
+
+/// Environment variable holding the log filter
+pub const LOG_ENV_VAR: &str = "NODE_DRIVER_LOG";
+
+/// Filter used when [`LOG_ENV_VAR`] is not set
+const DEFAULT_FILTER: &str = "info";
+
+/// Install a subscriber logging to stderr.
+///
+/// This does nothing if a global subscriber is already installed, so you are free to install your
+/// own before calling [`Maelstrom::init`](crate::Maelstrom::init).
+pub fn init() {
+    let filter =
+        EnvFilter::try_from_env(LOG_ENV_VAR).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let _ = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_ansi(false)
+        .with_env_filter(filter)
+        .event_format(NodeIdFormat(format::Format::default().compact()))
+        .try_init();
+}
+
+/// Event formatter prefixing events with the id of the node
+struct NodeIdFormat<F>(F);
+
+impl<S, N, F> FormatEvent<S, N> for NodeIdFormat<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        if let Some(node_id) = crate::node_id() {
+            write!(writer, "[{node_id}] ")?;
+        }
+        self.0.format_event(ctx, writer, event)
+    }
+}
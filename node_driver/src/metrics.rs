@@ -0,0 +1,250 @@
+//! Per-node metrics about the messages going through the [`InputInterface`](crate::InputInterface)
+//! and the [`OutputInterface`](crate::OutputInterface).
+//!
+//! The following metrics are collected automatically:
+//! - the number of messages received and sent, per message `type`,
+//! - the number of bytes received and sent, per message `type`, newlines excluded,
+//! - a histogram of the time spent handling each type of message, measured between the moment a
+//!   message is yielded by [`InputInterface::iter`](crate::InputInterface::iter) and the moment
+//!   the next one is requested. A node handing its messages to other threads, like through the
+//!   channel of an event loop, only gets the time it took to pass them on, not the time these
+//!   threads spent handling them,
+//! - a histogram of RPC round-trip times, between sending a message with a `msg_id` and receiving
+//!   the reply with the matching `in_reply_to`,
+//! - the number of requests still waiting for a reply.
+//!
+//! Every message sent with a `msg_id` and without an `in_reply_to` counts as a request waiting for
+//! a reply. Messages which don't expect one can be sent with
+//! [`OutputInterface::send_oneway`](crate::OutputInterface::send_oneway) so they don't end up
+//! counted as unanswered.
+//!
+//! Metrics are dumped as a single json object when the input reaches EOF, and whenever [`dump`]
+//! is called. The destination is configured by the [`METRICS_ENV_VAR`] environment variable:
+//! `stderr` (the default), `off`, or the path of a file to write.
+//!
+//! ```
+//! use node_driver::metrics;
+//!
+//! let snapshot = metrics::snapshot();
+//! println!("{}", serde_json::to_string_pretty(&snapshot).unwrap());
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{Envelope, NodeId};
+
+/// Environment variable configuring where metrics are dumped
+pub const METRICS_ENV_VAR: &str = "NODE_DRIVER_METRICS";
+
+/// Upper bounds of the histogram buckets, in microseconds. A last bucket catches everything else.
+const BUCKETS_US: [u64; 11] = [
+    10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000, 100_000, 500_000, 1_000_000,
+];
+
+/// Requests older than this are considered lost and stop counting as pending
+const PENDING_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A histogram of durations
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Histogram {
+    /// Number of recorded durations
+    pub count: u64,
+    /// Sum of the recorded durations, in microseconds
+    pub sum_us: u64,
+    /// Shortest recorded duration, in microseconds
+    pub min_us: Option<u64>,
+    /// Longest recorded duration, in microseconds
+    pub max_us: Option<u64>,
+    /// Non-empty buckets, in increasing order
+    pub buckets: Vec<Bucket>,
+}
+
+/// A bucket of a [`Histogram`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Bucket {
+    /// Upper bound of the bucket in microseconds, `None` for the last bucket
+    pub le_us: Option<u64>,
+    /// Number of durations in this bucket
+    pub count: u64,
+}
+
+impl Histogram {
+    /// Record a duration
+    pub fn record(&mut self, duration: Duration) {
+        let us = duration.as_micros() as u64;
+        self.count += 1;
+        self.sum_us += us;
+        self.min_us = Some(self.min_us.map_or(us, |min| min.min(us)));
+        self.max_us = Some(self.max_us.map_or(us, |max| max.max(us)));
+        let le_us = BUCKETS_US.iter().copied().find(|&bound| us <= bound);
+        // the last bucket has no bound, so sort it after all the others
+        let key = |bucket: &Bucket| bucket.le_us.unwrap_or(u64::MAX);
+        match self
+            .buckets
+            .binary_search_by_key(&le_us.unwrap_or(u64::MAX), key)
+        {
+            Ok(i) => self.buckets[i].count += 1,
+            Err(i) => self.buckets.insert(i, Bucket { le_us, count: 1 }),
+        }
+    }
+}
+
+/// A snapshot of the metrics of a node, as dumped in json
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Metrics {
+    /// Id of the node, once it has been initialized
+    pub node_id: Option<String>,
+    /// Number of messages received, per type
+    pub received: BTreeMap<String, u64>,
+    /// Number of messages sent, per type
+    pub sent: BTreeMap<String, u64>,
+    /// Size of the messages received, in bytes, per type
+    pub received_bytes: BTreeMap<String, u64>,
+    /// Size of the messages sent, in bytes, per type
+    pub sent_bytes: BTreeMap<String, u64>,
+    /// Time spent handling messages, per type, until the next message is read
+    pub handler_duration: BTreeMap<String, Histogram>,
+    /// Round-trip time of requests which got a reply
+    pub rpc_round_trip: Histogram,
+    /// Number of requests waiting for a reply
+    pub pending_requests: usize,
+    /// Number of requests which never got a reply
+    pub unanswered_requests: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    metrics: Metrics,
+    /// (destination, msg_id) -> send time of requests waiting for a reply
+    pending: HashMap<(NodeId, usize), Instant>,
+    /// Requests in the order they were sent, to expire them without scanning `pending`. Requests
+    /// which got a reply stay here until they would have expired.
+    sent_order: VecDeque<(Instant, (NodeId, usize))>,
+}
+
+impl Registry {
+    /// Stop waiting for the requests sent more than [`PENDING_REQUEST_TIMEOUT`] ago
+    fn expire(&mut self, now: Instant) {
+        while let Some((sent_at, key)) = self.sent_order.pop_front() {
+            if now - sent_at < PENDING_REQUEST_TIMEOUT {
+                self.sent_order.push_front((sent_at, key));
+                break;
+            }
+            // the request may have been answered, or sent again with the same msg_id since
+            if self.pending.get(&key) == Some(&sent_at) {
+                self.pending.remove(&key);
+                self.metrics.unanswered_requests += 1;
+            }
+        }
+    }
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+fn with_registry<T>(f: impl FnOnce(&mut Registry) -> T) -> T {
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut registry)
+}
+
+/// Measures the time spent handling a received message, recorded when dropped
+pub(crate) struct HandlerTimer {
+    message_type: String,
+    start: Instant,
+}
+
+impl Drop for HandlerTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let message_type = std::mem::take(&mut self.message_type);
+        with_registry(|r| {
+            r.metrics
+                .handler_duration
+                .entry(message_type)
+                .or_default()
+                .record(elapsed)
+        });
+    }
+}
+
+/// Record a message received as a json line, and start timing its handling
+pub(crate) fn record_received(line: &str) -> Option<HandlerTimer> {
+    let envelope: Envelope = Envelope::parse(line).ok()?;
+    let now = Instant::now();
+    with_registry(|r| {
+        *r.metrics
+            .received
+            .entry(envelope.body.msg_type.clone())
+            .or_default() += 1;
+        *r.metrics
+            .received_bytes
+            .entry(envelope.body.msg_type.clone())
+            .or_default() += line.len() as u64;
+        if let Some(in_reply_to) = envelope.body.in_reply_to {
+            if let Some(sent_at) = r.pending.remove(&(envelope.src, in_reply_to)) {
+                r.metrics.rpc_round_trip.record(now - sent_at);
+            }
+        }
+        r.metrics.pending_requests = r.pending.len();
+    });
+    Some(HandlerTimer {
+        message_type: envelope.body.msg_type,
+        start: now,
+    })
+}
+
+/// Record a message sent as a json line
+pub(crate) fn record_sent(line: &str) {
+    let Ok(envelope) = Envelope::parse(line) else {
+        return;
+    };
+    let now = Instant::now();
+    with_registry(|r| {
+        *r.metrics
+            .sent_bytes
+            .entry(envelope.body.msg_type.clone())
+            .or_default() += line.len() as u64;
+        *r.metrics.sent.entry(envelope.body.msg_type).or_default() += 1;
+        // only requests expect a reply, replies themselves are never acknowledged
+        // and messages sent without a msg_id don't expect one either
+        if let (Some(msg_id), None) = (envelope.body.msg_id, envelope.body.in_reply_to) {
+            let key = (envelope.dst, msg_id);
+            r.pending.insert(key.clone(), now);
+            r.sent_order.push_back((now, key));
+        }
+        r.expire(now);
+        r.metrics.pending_requests = r.pending.len();
+    });
+}
+
+/// Obtain a copy of the current metrics
+pub fn snapshot() -> Metrics {
+    let mut metrics = with_registry(|r| r.metrics.clone());
+    metrics.node_id = crate::node_id().map(String::from);
+    metrics
+}
+
+/// Dump the current metrics to the destination configured by [`METRICS_ENV_VAR`]
+pub fn dump() -> anyhow::Result<()> {
+    let destination = std::env::var(METRICS_ENV_VAR).unwrap_or_default();
+    let json = serde_json::to_string(&snapshot()).context("Serializing metrics")?;
+    match destination.as_str() {
+        "off" => Ok(()),
+        "" | "stderr" => {
+            eprintln!("{json}");
+            Ok(())
+        }
+        path => {
+            std::fs::write(path, json + "\n").with_context(|| format!("Writing metrics to {path}"))
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+/// The identifier of a participant of the Maelstrom network: a node, a client or a service.
+///
+/// Maelstrom names nodes `n0`, `n1`..., clients `c0`, `c1`... and services after what they do,
+/// like `lin-kv` or `seq-kv`. [`NodeId::kind`] tells them apart, and ids are ordered by kind and
+/// then by number, so `n2` comes before `n10`.
+///
+/// Since this is not the order of their strings, maps keyed by [`NodeId`] are searched with a
+/// [`NodeId`] rather than a `&str`.
+///
+/// ```
+/// use node_driver::{NodeId, NodeKind};
+///
+/// let node: NodeId = "n10".parse().unwrap();
+/// assert_eq!(node.kind(), NodeKind::Node);
+/// assert_eq!(NodeId::from("c1").kind(), NodeKind::Client);
+/// assert_eq!(NodeId::from("lin-kv").kind(), NodeKind::Service);
+///
+/// let mut ids = vec![node, NodeId::from("lin-kv"), NodeId::from("n2"), NodeId::from("c1")];
+/// ids.sort();
+/// assert_eq!(ids, ["c1", "n2", "n10", "lin-kv"]);
+///
+/// let set: std::collections::BTreeSet<NodeId> = ids.into_iter().collect();
+/// assert!(set.contains(&NodeId::from("n10")));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NodeId(String);
+
+/// The kind of participant a [`NodeId`] designates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NodeKind {
+    /// A client sending requests to the nodes, like `c1`
+    Client,
+    /// A node of the cluster, like `n1`
+    Node,
+    /// A service provided by Maelstrom, like `lin-kv`
+    Service,
+}
+
+impl NodeId {
+    /// Create an id, without checking its format
+    pub fn new(id: impl Into<String>) -> Self {
+        Self(id.into())
+    }
+
+    /// The id as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The kind of participant this id designates
+    pub fn kind(&self) -> NodeKind {
+        match (self.0.chars().next(), self.number()) {
+            (Some('n'), Some(_)) => NodeKind::Node,
+            (Some('c'), Some(_)) => NodeKind::Client,
+            _ => NodeKind::Service,
+        }
+    }
+
+    /// The number of a node or client, e.g. `3` for `n3`
+    pub fn number(&self) -> Option<usize> {
+        let digits = self.0.get(1..)?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = anyhow::Error;
+
+    /// Parse an id, which must be non empty and cannot contain whitespace
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.chars().any(char::is_whitespace) {
+            bail!("Invalid node id {s:?}");
+        }
+        Ok(Self::new(s))
+    }
+}
+
+impl Ord for NodeId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.kind()
+            .cmp(&other.kind())
+            .then_with(|| self.number().cmp(&other.number()))
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for NodeId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for NodeId {
+    fn from(id: &str) -> Self {
+        Self::new(id)
+    }
+}
+
+impl From<String> for NodeId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl From<NodeId> for String {
+    fn from(id: NodeId) -> Self {
+        id.0
+    }
+}
+
+impl AsRef<str> for NodeId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for NodeId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for NodeId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialEq<NodeId> for str {
+    fn eq(&self, other: &NodeId) -> bool {
+        self == other.0
+    }
+}
+
+impl PartialEq<NodeId> for &str {
+    fn eq(&self, other: &NodeId) -> bool {
+        *self == other.0
+    }
+}
@@ -0,0 +1,169 @@
+//! Payloads following the Maelstrom conventions.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{Body, Message};
+
+/// A payload enum whose variants are the types of messages of the Maelstrom protocol, and which
+/// knows which of its messages are requests and which are their replies.
+///
+/// This can also be a single message type, as a struct: see [`Request`].
+///
+/// This is implemented with `#[derive(MaelstromPayload)]`, which also implements [`Serialize`]
+/// and [`Deserialize`](serde::Deserialize) like the `#[serde(tag = "type")]` and
+/// `#[serde(rename_all = "snake_case")]` attributes would: the variant `EchoOk` is serialized as
+/// an object whose `type` is `echo_ok`. Other `#[serde]` attributes can still be used on the
+/// enum, its variants and their fields, and a `#[serde(rename_all = "...")]` attribute on the
+/// enum replaces the snake case convention.
+///
+/// A variant `Foo` is a request whose reply is the variant `FooOk`, if it exists. Other pairs can
+/// be declared with `#[maelstrom(reply = "type")]`, where the reply type may be a message defined
+/// elsewhere, like the `error` messages of Maelstrom.
+///
+/// ```
+/// use node_driver::MaelstromPayload;
+///
+/// #[derive(Debug, MaelstromPayload)]
+/// enum KvPayload {
+///     Read { key: usize },
+///     ReadOk { value: usize },
+///     #[serde(rename = "cas")]
+///     CompareAndSwap { key: usize, from: usize, to: usize },
+///     #[maelstrom(reply = "cas_ok")]
+///     Write { key: usize, value: usize },
+///     CasOk,
+/// }
+///
+/// let read: KvPayload = serde_json::from_str(r#"{"type":"read","key":1}"#).unwrap();
+/// assert_eq!(read.msg_type(), "read");
+/// assert_eq!(read.reply_type(), Some("read_ok"));
+/// assert_eq!(serde_json::to_string(&KvPayload::CasOk).unwrap(), r#"{"type":"cas_ok"}"#);
+///
+/// assert_eq!(KvPayload::TYPES, ["read", "read_ok", "cas", "write", "cas_ok"]);
+/// assert_eq!(KvPayload::REPLIES, [("read", "read_ok"), ("cas", "cas_ok"), ("write", "cas_ok")]);
+/// assert_eq!(KvPayload::CasOk.request_types().collect::<Vec<_>>(), ["cas", "write"]);
+///
+/// #[derive(Debug, MaelstromPayload)]
+/// #[serde(rename_all = "kebab-case")]
+/// enum TxnPayload {
+///     ReadAll,
+///     ReadAllOk { values: Vec<usize> },
+/// }
+///
+/// assert_eq!(TxnPayload::TYPES, ["read-all", "read-all-ok"]);
+/// assert_eq!(TxnPayload::REPLIES, [("read-all", "read-all-ok")]);
+/// assert_eq!(serde_json::to_string(&TxnPayload::ReadAll).unwrap(), r#"{"type":"read-all"}"#);
+/// ```
+pub trait MaelstromPayload: Serialize + DeserializeOwned {
+    /// The `type` of each variant, in declaration order
+    const TYPES: &'static [&'static str];
+
+    /// The `(request, reply)` pairs of types
+    const REPLIES: &'static [(&'static str, &'static str)];
+
+    /// The `type` of this message
+    fn msg_type(&self) -> &'static str;
+
+    /// Whether the given `type` is the one of a variant of this payload
+    fn has_type(msg_type: &str) -> bool {
+        Self::TYPES.contains(&msg_type)
+    }
+
+    /// The `type` of the reply to this message, if it is a request
+    fn reply_type(&self) -> Option<&'static str> {
+        let msg_type = self.msg_type();
+        Self::REPLIES
+            .iter()
+            .find(|(request, _)| *request == msg_type)
+            .map(|(_, reply)| *reply)
+    }
+
+    /// The `type`s of the requests this message can be a reply to
+    fn request_types(&self) -> impl Iterator<Item = &'static str> {
+        let msg_type = self.msg_type();
+        Self::REPLIES
+            .iter()
+            .filter(move |(_, reply)| *reply == msg_type)
+            .map(|(request, _)| *request)
+    }
+
+    /// Whether this message is a reply to a request of this payload
+    fn is_reply(&self) -> bool {
+        self.request_types().next().is_some()
+    }
+}
+
+/// A request payload, whose reply can only be a [`Request::Response`].
+///
+/// This makes it a compile-time error to answer a request with the wrong message, through
+/// [`Message::reply`], or to expect the wrong reply to an [RPC](crate::rpc). Requests and
+/// responses are structs deriving [`MaelstromPayload`], like the variants of a payload enum would
+/// be: their `type` is the snake case name of the struct. `#[maelstrom(response = Type)]`
+/// implements [`Request`] for a struct.
+///
+/// ```
+/// use node_driver::{Message, MaelstromPayload};
+///
+/// #[derive(Debug, MaelstromPayload)]
+/// #[maelstrom(response = ReadOk)]
+/// struct Read {}
+///
+/// #[derive(Debug, MaelstromPayload)]
+/// struct ReadOk {
+///     messages: Vec<usize>,
+/// }
+///
+/// let request: Message<Read> =
+///     serde_json::from_str(r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}"#).unwrap();
+/// let reply = request.reply(ReadOk { messages: vec![1, 2] });
+/// assert_eq!(reply.dst, "c1");
+/// assert_eq!(reply.body.in_reply_to, Some(3));
+/// assert_eq!(Read::REPLIES, [("read", "read_ok")]);
+/// assert_eq!(
+///     serde_json::to_string(&reply.body.payload).unwrap(),
+///     r#"{"type":"read_ok","messages":[1,2]}"#
+/// );
+///
+/// // a message of another type is not a `read`
+/// let topology = r#"{"src":"c1","dest":"n1","body":{"type":"topology","msg_id":4}}"#;
+/// assert!(serde_json::from_str::<Message<Read>>(topology).is_err());
+/// assert!(serde_json::from_str::<Read>(r#"{"messages":[]}"#).is_err());
+/// ```
+///
+/// Replying with another payload does not compile:
+///
+/// ```compile_fail
+/// # use node_driver::{Message, MaelstromPayload};
+/// # #[derive(Debug, MaelstromPayload)]
+/// # #[maelstrom(response = ReadOk)]
+/// # struct Read {}
+/// # #[derive(Debug, MaelstromPayload)]
+/// # struct ReadOk {}
+/// #[derive(Debug, MaelstromPayload)]
+/// struct TopologyOk {}
+///
+/// # let request: Message<Read> =
+/// #     serde_json::from_str(r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}"#).unwrap();
+/// let reply = request.reply(TopologyOk {});
+/// ```
+pub trait Request: MaelstromPayload {
+    /// The payload of the reply to this request
+    type Response: MaelstromPayload;
+}
+
+impl<R: Request> Message<R> {
+    /// Build the reply to this request, addressed to its sender.
+    ///
+    /// Like [`Message::to_response`], the reply is linked to the request with `in_reply_to`. Its
+    /// `msg_id` is left for the [`OutputInterface`](crate::OutputInterface) to fill.
+    pub fn reply(&self, response: R::Response) -> Message<R::Response> {
+        Message {
+            src: self.dst.clone(),
+            dst: self.src.clone(),
+            body: Body {
+                in_reply_to: self.body.msg_id,
+                ..Body::new(response)
+            },
+        }
+    }
+}
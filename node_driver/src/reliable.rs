@@ -0,0 +1,319 @@
+//! Reliable broadcast of values to every node, despite lost messages and network partitions.
+//!
+//! A [`ReliableBroadcast`] forwards every value it did not know yet to the neighbours of the
+//! node, except the one it came from, in a `forward` message. Each `forward` message is sent again
+//! on [`ReliableBroadcast::retry`] until the neighbour acknowledges it with a `forward_ok`, however
+//! long a partition lasts. Values received several times are acknowledged but only forwarded the
+//! first time. Values received before the node knows its neighbours are forwarded once it does.
+//!
+//! The node sends the messages it builds, hands the messages it receives to
+//! [`ReliableBroadcast::handle`] and calls [`ReliableBroadcast::retry`] regularly, e.g. with
+//! [`ReliableBroadcast::ticker`].
+//!
+//! ```
+//! use std::time::Instant;
+//! use node_driver::{reliable::ReliableBroadcast, NodeId, NodeMetadata};
+//!
+//! let cluster: Vec<NodeId> = vec!["n1".into(), "n2".into(), "n3".into()];
+//! let n1 = NodeMetadata::with_cluster("n1".into(), cluster.clone(), 1).unwrap();
+//! let n2 = NodeMetadata::with_cluster("n2".into(), cluster.clone(), 1).unwrap();
+//! let mut broadcast_1 = ReliableBroadcast::new(&n1);
+//! let mut broadcast_2 = ReliableBroadcast::new(&n2);
+//!
+//! // n1 forwards a new value to its neighbours
+//! let forwards = broadcast_1.insert(4, &["n2".into(), "n3".into()]);
+//! assert_eq!(forwards.len(), 2);
+//! assert!(broadcast_1.insert(4, &["n2".into(), "n3".into()]).is_empty());
+//!
+//! // n2 learns it, acknowledges it and forwards it to its other neighbours only
+//! let handled = broadcast_2.handle(forwards[0].clone(), &["n1".into(), "n3".into()]);
+//! assert_eq!(handled.new_value, Some(4));
+//! assert_eq!(handled.messages.len(), 2);
+//! assert_eq!(handled.messages[0].dst, "n1");
+//! assert_eq!(handled.messages[1].dst, "n3");
+//!
+//! // n1 gets the acknowledgement of n2, but n3 is partitioned away
+//! broadcast_1.handle(handled.messages[0].clone(), &[]);
+//! let retries = broadcast_1.retry(Instant::now() + broadcast_1.retry_interval(), &["n2".into(), "n3".into()]);
+//! assert_eq!(retries.len(), 1);
+//! assert_eq!(retries[0].dst, "n3");
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{round, MaelstromPayload, Message, MsgIdAllocator, NodeId, NodeMetadata};
+
+/// The messages exchanged by [`ReliableBroadcast`], generic over the broadcast values
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardPayload<T> {
+    /// A value to deliver to every node
+    Forward {
+        /// the value
+        value: T,
+    },
+    /// Acknowledges the `forward` message it replies to
+    ForwardOk,
+}
+
+impl<T: Serialize + DeserializeOwned> MaelstromPayload for ForwardPayload<T> {
+    const TYPES: &'static [&'static str] = &["forward", "forward_ok"];
+    const REPLIES: &'static [(&'static str, &'static str)] = &[("forward", "forward_ok")];
+
+    fn msg_type(&self) -> &'static str {
+        match self {
+            Self::Forward { .. } => "forward",
+            Self::ForwardOk => "forward_ok",
+        }
+    }
+}
+
+/// What [`ReliableBroadcast::handle`] did with a message
+#[derive(Debug)]
+pub struct Handled<T> {
+    /// The value of a `forward` message, if the node did not know it before
+    pub new_value: Option<T>,
+    /// The messages to send: the acknowledgement of a `forward` message, followed by the
+    /// forwarding of its value if it is new
+    pub messages: Vec<Message<ForwardPayload<T>>>,
+}
+
+/// The number of attempts to send a value to a neighbour whose acknowledgement is still accepted
+const KEPT_ATTEMPTS: usize = 4;
+
+/// A value sent to a neighbour and not acknowledged yet
+#[derive(Debug)]
+struct Unacked {
+    last_sent: Instant,
+    /// the `msg_id`s of the last attempts, any of them can be acknowledged
+    msg_ids: VecDeque<usize>,
+}
+
+/// Delivers values to every node, see the [module docs](self).
+#[derive(Debug)]
+pub struct ReliableBroadcast<T> {
+    node_id: NodeId,
+    msg_ids: MsgIdAllocator,
+    values: HashSet<T>,
+    unacked: HashMap<(NodeId, T), Unacked>,
+    /// the value and neighbour of the `forward` messages sent, by `msg_id`
+    attempts: HashMap<usize, (NodeId, T)>,
+    /// the values received before the neighbours were known, with the node they came from
+    queued: Vec<(T, Option<NodeId>)>,
+    retry_interval: Duration,
+}
+
+impl<T> ReliableBroadcast<T>
+where
+    T: Clone + Eq + Hash + Serialize + DeserializeOwned,
+{
+    /// Create a broadcast without any value, sending messages from this node.
+    ///
+    /// By default, values are sent again after 200ms without acknowledgement.
+    pub fn new(metadata: &NodeMetadata) -> Self {
+        Self {
+            node_id: metadata.node_id.clone(),
+            msg_ids: metadata.msg_id_allocator(),
+            values: HashSet::new(),
+            unacked: HashMap::new(),
+            attempts: HashMap::new(),
+            queued: Vec::new(),
+            retry_interval: Duration::from_millis(200),
+        }
+    }
+
+    /// Set the time to wait for an acknowledgement before sending a value again
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// The time to wait for an acknowledgement before sending a value again
+    pub fn retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+
+    /// The values known by the node
+    pub fn values(&self) -> &HashSet<T> {
+        &self.values
+    }
+
+    /// The number of values sent to a neighbour which did not acknowledge them yet
+    pub fn unacked_count(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Broadcast a value received by this node, building the `forward` messages to its
+    /// `neighbours`, or nothing if the value is already known.
+    ///
+    /// Without any neighbour, the topology is not known yet: the value is forwarded on the first
+    /// call given some neighbours.
+    ///
+    /// ```
+    /// use std::time::Instant;
+    /// use node_driver::{reliable::ReliableBroadcast, NodeMetadata};
+    ///
+    /// let n1 = NodeMetadata::with_cluster("n1".into(), vec!["n1".into(), "n2".into()], 1).unwrap();
+    /// let mut broadcast = ReliableBroadcast::new(&n1);
+    /// assert!(broadcast.insert(4, &[]).is_empty());
+    /// assert!(broadcast.values().contains(&4));
+    ///
+    /// // the topology arrived
+    /// let forwards = broadcast.retry(Instant::now(), &["n2".into()]);
+    /// assert_eq!(forwards.len(), 1);
+    /// assert_eq!(forwards[0].dst, "n2");
+    /// ```
+    pub fn insert(&mut self, value: T, neighbours: &[NodeId]) -> Vec<Message<ForwardPayload<T>>> {
+        self.broadcast(value, None, neighbours)
+    }
+
+    /// Forward a new value to the `neighbours` other than `source`, or queue it until the
+    /// neighbours are known
+    fn broadcast(
+        &mut self,
+        value: T,
+        source: Option<NodeId>,
+        neighbours: &[NodeId],
+    ) -> Vec<Message<ForwardPayload<T>>> {
+        let mut forwards = self.forward_queued(neighbours);
+        if !self.values.insert(value.clone()) {
+            return forwards;
+        }
+        if neighbours.is_empty() {
+            self.queued.push((value, source));
+            return forwards;
+        }
+        let now = Instant::now();
+        let targets: Vec<NodeId> = neighbours
+            .iter()
+            .filter(|&neighbour| *neighbour != self.node_id && Some(neighbour) != source.as_ref())
+            .cloned()
+            .collect();
+        forwards.extend(
+            targets
+                .into_iter()
+                .map(|neighbour| self.forward(neighbour, value.clone(), now)),
+        );
+        forwards
+    }
+
+    /// Forward the values queued until the neighbours were known, if they are now
+    fn forward_queued(&mut self, neighbours: &[NodeId]) -> Vec<Message<ForwardPayload<T>>> {
+        if neighbours.is_empty() || self.queued.is_empty() {
+            return Vec::new();
+        }
+        let now = Instant::now();
+        let mut forwards = Vec::new();
+        for (value, source) in std::mem::take(&mut self.queued) {
+            for neighbour in neighbours {
+                if *neighbour != self.node_id && Some(neighbour) != source.as_ref() {
+                    forwards.push(self.forward(neighbour.clone(), value.clone(), now));
+                }
+            }
+        }
+        forwards
+    }
+
+    /// Handle a message of another node: acknowledge a `forward` message and forward its value to
+    /// the other `neighbours` if it is new, or record the acknowledgement of a `forward_ok` one
+    pub fn handle(&mut self, msg: Message<ForwardPayload<T>>, neighbours: &[NodeId]) -> Handled<T> {
+        match &msg.body.payload {
+            ForwardPayload::Forward { value } => {
+                let value = value.clone();
+                let source = msg.src.clone();
+                let ack = msg.to_response(Some(self.msg_ids.next_id()), ForwardPayload::ForwardOk);
+                let is_new = !self.values.contains(&value);
+                // the sender has the value, it does not need it back
+                let forwards = self.broadcast(value.clone(), Some(source), neighbours);
+                Handled {
+                    new_value: is_new.then_some(value),
+                    messages: std::iter::once(ack).chain(forwards).collect(),
+                }
+            }
+            ForwardPayload::ForwardOk => {
+                if let Some(key) = msg
+                    .body
+                    .in_reply_to
+                    .and_then(|msg_id| self.attempts.remove(&msg_id))
+                {
+                    if let Some(unacked) = self.unacked.remove(&key) {
+                        for msg_id in unacked.msg_ids {
+                            self.attempts.remove(&msg_id);
+                        }
+                    }
+                }
+                Handled {
+                    new_value: None,
+                    messages: Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Build the `forward` messages which were not acknowledged within the retry interval, as of
+    /// `now`, and those of the values received before the `neighbours` were known
+    pub fn retry(
+        &mut self,
+        now: Instant,
+        neighbours: &[NodeId],
+    ) -> Vec<Message<ForwardPayload<T>>> {
+        let mut forwards = self.forward_queued(neighbours);
+        let due: Vec<(NodeId, T)> = self
+            .unacked
+            .iter()
+            .filter(|(_, unacked)| now.duration_since(unacked.last_sent) >= self.retry_interval)
+            .map(|(key, _)| key.clone())
+            .collect();
+        forwards.extend(
+            due.into_iter()
+                .map(|(neighbour, value)| self.forward(neighbour, value, now)),
+        );
+        forwards
+    }
+
+    /// Spawn a thread calling `on_tick` every retry interval, for the node to call
+    /// [`ReliableBroadcast::retry`], see [`round::ticker`]
+    pub fn ticker(&self, on_tick: impl FnMut() -> bool + Send + 'static) -> JoinHandle<()> {
+        round::ticker(self.retry_interval, on_tick)
+    }
+
+    /// Build a `forward` message and wait for its acknowledgement
+    fn forward(&mut self, neighbour: NodeId, value: T, now: Instant) -> Message<ForwardPayload<T>> {
+        let msg = round::message(
+            &self.node_id,
+            neighbour.clone(),
+            &self.msg_ids,
+            None,
+            ForwardPayload::Forward {
+                value: value.clone(),
+            },
+        );
+        let msg_id = msg.body.msg_id.expect("the message has a msg_id");
+        self.attempts
+            .insert(msg_id, (neighbour.clone(), value.clone()));
+        let unacked = self
+            .unacked
+            .entry((neighbour.clone(), value.clone()))
+            .or_insert_with(|| Unacked {
+                last_sent: now,
+                msg_ids: VecDeque::new(),
+            });
+        unacked.last_sent = now;
+        unacked.msg_ids.push_back(msg_id);
+        // the acknowledgement of an older attempt is most likely lost for good
+        if unacked.msg_ids.len() > KEPT_ATTEMPTS {
+            if let Some(oldest) = unacked.msg_ids.pop_front() {
+                self.attempts.remove(&oldest);
+            }
+        }
+        msg
+    }
+}
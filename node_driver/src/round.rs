@@ -0,0 +1,133 @@
+//! Helpers shared by the components exchanging values in rounds, like
+//! [`Gossip`](crate::gossip::Gossip) or [`AntiEntropy`](crate::anti_entropy::AntiEntropy).
+//!
+//! These components only build messages: the node sends them, hands them the messages it
+//! receives, and starts their rounds regularly. [`ticker`] spawns the thread telling the node
+//! when a round is due:
+//!
+//! ```
+//! use std::{sync::mpsc, time::Duration};
+//! use node_driver::round::ticker;
+//!
+//! let (tx, rx) = mpsc::channel();
+//! let handle = ticker(Duration::from_millis(1), move || tx.send(()).is_ok());
+//! rx.recv().unwrap();
+//! rx.recv().unwrap();
+//!
+//! // the thread stops once the receiver hung up
+//! drop(rx);
+//! handle.join().unwrap();
+//! ```
+
+use std::{
+    hash::{Hash, Hasher},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{Body, Message, MsgIdAllocator, NodeId};
+
+/// Spawn a thread calling `on_tick` every `interval`, until it returns `false`.
+///
+/// `on_tick` usually sends an event to the main loop of the node, which then starts a round.
+pub fn ticker(
+    interval: Duration,
+    mut on_tick: impl FnMut() -> bool + Send + 'static,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while on_tick() {
+            thread::sleep(interval);
+        }
+    })
+}
+
+/// Build a message from `src` with a new `msg_id`
+pub(crate) fn message<P>(
+    src: &NodeId,
+    dst: NodeId,
+    msg_ids: &MsgIdAllocator,
+    in_reply_to: Option<usize>,
+    payload: P,
+) -> Message<P> {
+    Message {
+        src: src.clone(),
+        dst,
+        body: Body {
+            msg_id: Some(msg_ids.next_id()),
+            in_reply_to,
+            ..Body::new(payload)
+        },
+    }
+}
+
+/// Hash a value with [`StableHasher`]
+pub(crate) fn stable_hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A hasher giving the same hashes on every node, whatever their platform or Rust version, for
+/// the hashes sent over the network.
+///
+/// The algorithm of the hasher of the standard library is unspecified, so this is FNV-1a, with the
+/// finalizer of MurmurHash3 for its low bits to depend on every input byte. Integers are hashed
+/// as little-endian 64 bits values.
+#[derive(Debug, Clone)]
+pub(crate) struct StableHasher {
+    state: u64,
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self {
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= u64::from(byte);
+            self.state = self.state.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i.into());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = self.state;
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        hash ^ (hash >> 33)
+    }
+}
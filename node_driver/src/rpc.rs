@@ -0,0 +1,212 @@
+//! Typed remote procedure calls between nodes and services.
+//!
+//! An [`RpcClient`] sends [`Request`]s and matches the messages it is given back to the requests
+//! they reply to. Waiting for a reply gives a [`Message`] of the [`Request::Response`] type of the
+//! request, or an [`RpcError`] if the other side answered with an `error` message.
+//!
+//! The client does not read messages itself: the main loop of the node hands every message it
+//! reads to [`RpcClient::handle`], which keeps the replies and gives back the other messages. The
+//! calls are usually made, and waited for, from other threads.
+//!
+//! ```
+//! use std::time::Duration;
+//! use node_driver::{rpc::RpcClient, DynMessage, MaelstromPayload, NodeMetadata, OutputInterface};
+//!
+//! #[derive(Debug, MaelstromPayload)]
+//! #[maelstrom(response = ReadOk)]
+//! struct Read {
+//!     key: usize,
+//! }
+//!
+//! #[derive(Debug, MaelstromPayload)]
+//! struct ReadOk {
+//!     value: usize,
+//! }
+//!
+//! let metadata = NodeMetadata::with_cluster("n1".into(), vec!["n1".into()], 1).unwrap();
+//! let (sender, _writer) = OutputInterface::new(Vec::new()).into_sender();
+//! let rpc = RpcClient::new(&metadata, sender);
+//!
+//! let call = rpc.call("lin-kv", Read { key: 1 })?;
+//! // the main loop reads the reply and hands it to the client
+//! let reply: DynMessage = serde_json::from_str(
+//!     r#"{"src":"lin-kv","dest":"n1","body":{"type":"read_ok","in_reply_to":1,"value":4}}"#,
+//! )?;
+//! assert!(rpc.handle(reply).is_none());
+//!
+//! let reply = call.wait(Duration::from_secs(1))?;
+//! assert_eq!(reply.body.payload.value, 4);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+
+use crate::{
+    Body, DynMessage, MaelstromPayload, Message, MessageSender, MsgIdAllocator, NodeId,
+    NodeMetadata, Request,
+};
+
+type PendingCalls = Arc<Mutex<HashMap<usize, mpsc::Sender<DynMessage>>>>;
+
+/// Sends requests and delivers their replies, see the [module docs](self).
+///
+/// Clones share the same pending calls, so a call made with a clone can be answered through
+/// another one.
+#[derive(Debug, Clone)]
+pub struct RpcClient {
+    node_id: NodeId,
+    sender: MessageSender,
+    msg_ids: MsgIdAllocator,
+    pending: PendingCalls,
+}
+
+impl RpcClient {
+    /// Create a client sending requests from this node through the given sender
+    pub fn new(metadata: &NodeMetadata, sender: MessageSender) -> Self {
+        Self {
+            node_id: metadata.node_id.clone(),
+            sender,
+            msg_ids: metadata.msg_id_allocator(),
+            pending: Default::default(),
+        }
+    }
+
+    /// Send a request to `dst`, and obtain a handle to wait for its reply
+    pub fn call<R: Request>(
+        &self,
+        dst: impl Into<NodeId>,
+        request: R,
+    ) -> anyhow::Result<PendingCall<R::Response>> {
+        let msg_id = self.msg_ids.next_id();
+        let (tx, rx) = mpsc::channel();
+        self.lock().insert(msg_id, tx);
+        let call = PendingCall {
+            msg_id,
+            reply: rx,
+            pending: self.pending.clone(),
+            response: PhantomData,
+        };
+        self.sender.send_msg(Message {
+            src: self.node_id.clone(),
+            dst: dst.into(),
+            body: Body {
+                msg_id: Some(msg_id),
+                ..Body::new(request)
+            },
+        })?;
+        Ok(call)
+    }
+
+    /// Deliver the message to the call it replies to, if any, or give it back otherwise
+    pub fn handle(&self, msg: DynMessage) -> Option<DynMessage> {
+        let Some(in_reply_to) = msg.body.in_reply_to else {
+            return Some(msg);
+        };
+        let Some(call) = self.lock().remove(&in_reply_to) else {
+            return Some(msg);
+        };
+        // the caller may have stopped waiting, in which case the reply is dropped
+        let _ = call.send(msg);
+        None
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<usize, mpsc::Sender<DynMessage>>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A request sent with [`RpcClient::call`], waiting for its reply of type `P`
+#[derive(Debug)]
+pub struct PendingCall<P> {
+    msg_id: usize,
+    reply: mpsc::Receiver<DynMessage>,
+    pending: PendingCalls,
+    response: PhantomData<P>,
+}
+
+impl<P: MaelstromPayload> PendingCall<P> {
+    /// The `msg_id` of the request
+    pub fn msg_id(&self) -> usize {
+        self.msg_id
+    }
+
+    /// Wait for the reply, for at most `timeout`.
+    ///
+    /// This fails if no reply arrived in time, if the reply is an `error` message, in which case
+    /// the error can be downcast to an [`RpcError`], or if it is not of the response type.
+    pub fn wait(self, timeout: Duration) -> anyhow::Result<Message<P>> {
+        let reply = self.reply.recv_timeout(timeout).map_err(|_| {
+            anyhow!(
+                "No reply to request {} after {}ms",
+                self.msg_id,
+                timeout.as_millis()
+            )
+        })?;
+        match reply.payload_type() {
+            Some("error") => Err(RpcError::from_payload(&reply.body.payload).into()),
+            Some(msg_type) if P::has_type(msg_type) => reply.into_typed(),
+            msg_type => bail!(
+                "Unexpected reply of type {:?} to request {}",
+                msg_type.unwrap_or_default(),
+                self.msg_id
+            ),
+        }
+        .with_context(|| format!("While waiting for the reply to request {}", self.msg_id))
+    }
+}
+
+impl<P> Drop for PendingCall<P> {
+    fn drop(&mut self) {
+        // a reply arriving after this is not a reply to a known call anymore
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.msg_id);
+    }
+}
+
+/// An `error` message received in reply to a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    /// The error code, see the [Maelstrom protocol](https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors)
+    pub code: u64,
+    /// The description of the error, empty if there is none
+    pub text: String,
+}
+
+impl RpcError {
+    fn from_payload(payload: &Value) -> Self {
+        Self {
+            code: payload
+                .get("code")
+                .and_then(Value::as_u64)
+                .unwrap_or_default(),
+            text: payload
+                .get("text")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Error {}", self.code)?;
+        if !self.text.is_empty() {
+            write!(f, ": {}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RpcError {}
@@ -0,0 +1,165 @@
+//! A [`MessageSender`] that can be shared between threads.
+//!
+//! An [`OutputInterface`] needs to be borrowed mutably to send a message, which forces all the
+//! sending to happen in one place. Converting it into a [`MessageSender`] moves it to a dedicated
+//! writer thread, and gives back a handle that can be cloned and sent to any thread: timers,
+//! workers or callbacks can then all send messages concurrently, each message still being written
+//! as a single line.
+//!
+//! ```
+//! use node_driver::{Body, Message, OutputInterface};
+//!
+//! let (sender, writer) = OutputInterface::new(Vec::new()).into_sender();
+//! let threads: Vec<_> = (0..4)
+//!     .map(|i| {
+//!         let sender = sender.clone();
+//!         std::thread::spawn(move || {
+//!             sender.send_msg(Message {
+//!                 src: "n1".into(),
+//!                 dst: format!("n{}", i + 2).into(),
+//!                 body: Body::new(serde_json::json!({"type": "gossip"})),
+//!             })
+//!         })
+//!     })
+//!     .collect();
+//! for thread in threads {
+//!     thread.join().unwrap().unwrap();
+//! }
+//!
+//! // the writer thread stops once every sender has been dropped
+//! drop(sender);
+//! let output = writer.join().unwrap().into_inner().unwrap();
+//! assert_eq!(String::from_utf8(output).unwrap().lines().count(), 4);
+//! ```
+
+use std::{
+    io::Write,
+    sync::mpsc,
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+
+use crate::{Message, MsgIdAllocator, OutputInterface};
+
+/// A cloneable, thread-safe handle to send [`Message`]s, obtained with
+/// [`OutputInterface::into_sender`].
+///
+/// Messages are serialized by the calling thread and written by the writer thread in the order
+/// they were sent.
+#[derive(Debug, Clone)]
+pub struct MessageSender {
+    lines: mpsc::Sender<String>,
+    msg_ids: Option<MsgIdAllocator>,
+}
+
+impl MessageSender {
+    /// Send a [`Message<P>`] to the Maelstrom network
+    ///
+    /// Like [`OutputInterface::send_msg`], messages without a `msg_id` get one from the
+    /// [`MsgIdAllocator`] of the interface, if any.
+    ///
+    /// This fails if the message cannot be serialized, or if the writer thread has stopped.
+    /// Failing to write a message does not stop the writer thread: the error is logged, and the
+    /// next messages are still written.
+    pub fn send_msg<P>(&self, mut msg: Message<P>) -> anyhow::Result<()>
+    where
+        P: Serialize,
+    {
+        if let Some(msg_ids) = &self.msg_ids {
+            msg.body.msg_id.get_or_insert_with(|| msg_ids.next_id());
+        }
+        self.send_oneway(msg)
+    }
+
+    /// Send a [`Message<P>`] which expects no reply, like [`OutputInterface::send_oneway`]
+    pub fn send_oneway<P>(&self, msg: Message<P>) -> anyhow::Result<()>
+    where
+        P: Serialize,
+    {
+        let line = serde_json::to_string(&msg).context("Serializing message")?;
+        self.lines
+            .send(line)
+            .map_err(|_| anyhow!("The writer thread has stopped"))
+    }
+}
+
+/// Handle to the writer thread spawned by [`OutputInterface::into_sender`]
+pub struct WriterHandle<W> {
+    handle: JoinHandle<OutputInterface<W>>,
+}
+
+impl<W> WriterHandle<W> {
+    /// Wait for the writer thread to finish, which happens once all the [`MessageSender`]s have
+    /// been dropped, and get the [`OutputInterface`] back.
+    pub fn join(self) -> anyhow::Result<OutputInterface<W>> {
+        self.handle
+            .join()
+            .map_err(|_| anyhow!("The writer thread panicked"))
+    }
+}
+
+impl<W> OutputInterface<W>
+where
+    W: Write + Send + 'static,
+{
+    /// Move this interface to a dedicated writer thread, and obtain a [`MessageSender`] to send
+    /// messages from any thread.
+    ///
+    /// The writer thread keeps running when writing a message fails:
+    ///
+    /// ```
+    /// use std::io::{self, Write};
+    /// use node_driver::{Body, Message, OutputInterface};
+    ///
+    /// /// A writer failing to write the first message
+    /// #[derive(Default)]
+    /// struct Flaky(Vec<u8>, bool);
+    ///
+    /// impl Write for Flaky {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         if !std::mem::replace(&mut self.1, true) {
+    ///             return Err(io::ErrorKind::BrokenPipe.into());
+    ///         }
+    ///         self.0.write(buf)
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let (sender, writer) = OutputInterface::new(Flaky::default()).into_sender();
+    /// for message in ["lost", "sent"] {
+    ///     sender
+    ///         .send_msg(Message {
+    ///             src: "n1".into(),
+    ///             dst: "n2".into(),
+    ///             body: Body::new(serde_json::json!({"type": "echo", "echo": message})),
+    ///         })
+    ///         .unwrap();
+    /// }
+    /// drop(sender);
+    /// let output = writer.join().unwrap().into_inner().unwrap().0;
+    /// let output = String::from_utf8(output).unwrap();
+    /// assert!(!output.contains("lost") && output.contains("sent"));
+    /// ```
+    pub fn into_sender(mut self) -> (MessageSender, WriterHandle<W>) {
+        let (tx, rx) = mpsc::channel::<String>();
+        let msg_ids = self.msg_ids.clone();
+        let handle = thread::spawn(move || {
+            for line in rx {
+                // a message that could not be written is lost, like one lost by the network
+                if let Err(e) = self.write_line(line) {
+                    tracing::error!(error = %e, "could not send message");
+                }
+            }
+            self
+        });
+        (
+            MessageSender { lines: tx, msg_ids },
+            WriterHandle { handle },
+        )
+    }
+}
@@ -0,0 +1,131 @@
+//! Protection of the protocol stream against stray writes to stdout.
+//!
+//! Maelstrom reads the messages of a node from its stdout, so any `println!` in a node produces an
+//! invalid line and makes the test fail. To make those mistakes harmless, the protocol is written
+//! to a duplicate of the original stdout file descriptor, and the process stdout is then redirected
+//! to stderr, which Maelstrom forwards to the node logs. This happens when the node is initialized:
+//! a `println!` before that still corrupts the protocol.
+//!
+//! ```no_run
+//! let (_, _, mut output) = node_driver::Maelstrom::init()?;
+//! // this ends up in the node logs instead of corrupting the protocol stream
+//! println!("debugging my node");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The redirection is only available on unix platforms, elsewhere the protocol is written to the
+//! regular stdout.
+
+use std::io::{self, Write};
+
+/// A handle to the stream carrying the Maelstrom protocol.
+///
+/// This is the original stdout of the process, which stays reachable once stdout has been
+/// redirected to stderr. Handles are cheap to copy and all write to the same stream: a call to
+/// `write_all` holds a process-wide lock until all its bytes are written, so the lines written by
+/// several threads never mix, even when a pipe splits them in several writes.
+#[derive(Debug, Clone, Copy)]
+pub struct ProtocolStdout {
+    #[cfg(unix)]
+    file: &'static std::fs::File,
+}
+
+impl ProtocolStdout {
+    /// Obtain a handle to the protocol stream.
+    ///
+    /// On the first call, this duplicates the stdout file descriptor and redirects stdout to
+    /// stderr. If this fails, a warning is logged and the protocol keeps using stdout.
+    pub fn get() -> Self {
+        #[cfg(unix)]
+        {
+            Self {
+                file: unix::protocol_file(),
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            Self {}
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Write for ProtocolStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _guard = unix::lock();
+        self.file.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let _guard = unix::lock();
+        self.file.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(not(unix))]
+impl Write for ProtocolStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stdout().lock().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(buf)?;
+        stdout.flush()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::File,
+        io::{self, Write},
+        os::fd::{AsFd, AsRawFd, OwnedFd},
+        sync::{Mutex, MutexGuard, OnceLock},
+    };
+
+    static PROTOCOL_FILE: OnceLock<File> = OnceLock::new();
+
+    /// Serializes the writes to the protocol file
+    static PROTOCOL_LOCK: Mutex<()> = Mutex::new(());
+
+    pub(super) fn lock() -> MutexGuard<'static, ()> {
+        PROTOCOL_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(super) fn protocol_file() -> &'static File {
+        PROTOCOL_FILE.get_or_init(|| match redirect_stdout() {
+            Ok(fd) => File::from(fd),
+            Err(e) => {
+                tracing::warn!(error = %e, "could not redirect stdout to stderr, println! will corrupt the protocol");
+                let fd = io::stdout()
+                    .as_fd()
+                    .try_clone_to_owned()
+                    .expect("stdout should be a valid file descriptor");
+                File::from(fd)
+            }
+        })
+    }
+
+    /// Duplicate stdout and point the stdout file descriptor to stderr, returning the duplicate.
+    fn redirect_stdout() -> io::Result<OwnedFd> {
+        let stdout = io::stdout();
+        // make sure nothing buffered by std ends up on the wrong stream
+        stdout.lock().flush()?;
+        let protocol = stdout.as_fd().try_clone_to_owned()?;
+        // SAFETY: both file descriptors are valid for the whole duration of the process, and dup2
+        // atomically replaces stdout so no other thread can observe it closed.
+        if unsafe { libc::dup2(io::stderr().as_raw_fd(), stdout.as_raw_fd()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(protocol)
+    }
+}
@@ -0,0 +1,220 @@
+//! The topology of the cluster: which nodes each node talks to.
+//!
+//! Maelstrom sends a `topology` message to every node at the start of some workloads, like
+//! broadcast, suggesting neighbours for each node. Instead of handling it in every node,
+//! [`InitBuilder::handle_topology`](crate::InitBuilder::handle_topology) lets the
+//! [`InputInterface`](crate::InputInterface) acknowledge it, store it in the
+//! [`NodeMetadata`](crate::NodeMetadata) and notify the node through a hook. `topology` messages
+//! are then never yielded to the node.
+//!
+//! ```
+//! use std::io::Cursor;
+//! use node_driver::{InitBuilder, InputInterface, OutputInterface};
+//!
+//! let lines = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2","n3"]}}
+//! {"src":"c1","dest":"n1","body":{"type":"topology","msg_id":2,"topology":{"n1":["n2"],"n2":["n1","n3"],"n3":["n2"]}}}
+//! {"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}"#;
+//! let (metadata, mut input, output) = InitBuilder::new(
+//!     InputInterface::new(Cursor::new(lines)),
+//!     OutputInterface::new(Vec::new()),
+//! )
+//! .handle_topology(|topology| println!("new topology: {topology:?}"))
+//! .init()
+//! .unwrap();
+//!
+//! // before the topology arrives, the node has no neighbour
+//! assert!(metadata.neighbours().is_empty());
+//!
+//! // the topology message is handled while reading the next messages
+//! let msg = input.iter::<serde_json::Value>().next().unwrap().unwrap();
+//! assert_eq!(msg.body.payload["type"], "read");
+//! assert_eq!(metadata.neighbours(), ["n2"]);
+//! assert_eq!(metadata.topology().unwrap().neighbours_of("n2"), ["n1", "n3"]);
+//!
+//! drop(input);
+//! let sent = String::from_utf8(output.into_inner().unwrap()).unwrap();
+//! assert!(sent.contains(r#""in_reply_to":2,"type":"topology_ok""#));
+//! ```
+//!
+//! Nodes may also ignore the suggested topology and compute their own from the ids of the
+//! cluster, with a [`Strategy`] chosen at startup through
+//! [`InitBuilder::topology_strategy`](crate::InitBuilder::topology_strategy). The topology is then
+//! known as soon as the node is initialized, and `topology` messages are only acknowledged:
+//!
+//! ```
+//! use std::io::Cursor;
+//! use node_driver::{topology::Strategy, InitBuilder, InputInterface, OutputInterface};
+//!
+//! let lines = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2","n3","n4"]}}
+//! {"src":"c1","dest":"n1","body":{"type":"topology","msg_id":2,"topology":{"n1":["n2"]}}}"#;
+//! let (metadata, mut input, _output) = InitBuilder::new(
+//!     InputInterface::new(Cursor::new(lines)),
+//!     OutputInterface::new(Vec::new()),
+//! )
+//! .topology_strategy(Strategy::Star)
+//! .init()
+//! .unwrap();
+//!
+//! assert_eq!(metadata.neighbours(), ["n2", "n3", "n4"]);
+//! assert!(input.iter::<serde_json::Value>().next().is_none());
+//! assert_eq!(metadata.neighbours(), ["n2", "n3", "n4"]);
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex, RwLock, Weak},
+};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{Envelope, Message, MsgIdAllocator, NodeId};
+
+mod strategy;
+
+pub use strategy::{Strategy, TOPOLOGY_ENV_VAR};
+
+/// The neighbours of each node of the cluster
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Topology {
+    neighbours: HashMap<NodeId, Vec<NodeId>>,
+}
+
+impl Topology {
+    /// Build a topology from the neighbours of each node
+    pub fn new(neighbours: HashMap<NodeId, Vec<NodeId>>) -> Self {
+        Self { neighbours }
+    }
+
+    /// The neighbours of the given node, empty if it is not part of the topology
+    pub fn neighbours_of(&self, node_id: &str) -> &[NodeId] {
+        self.neighbours
+            .get(&NodeId::from(node_id))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The neighbours of every node
+    pub fn as_map(&self) -> &HashMap<NodeId, Vec<NodeId>> {
+        &self.neighbours
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum TopologyPayload {
+    Topology { topology: Topology },
+    TopologyOk,
+}
+
+/// The topology of the node, shared between its [`NodeMetadata`](crate::NodeMetadata) and its
+/// [`InputInterface`](crate::InputInterface)
+pub(crate) type SharedTopology = Arc<RwLock<Option<Topology>>>;
+
+type Hook = Box<dyn FnMut(&Topology) + Send>;
+
+/// Where to acknowledge `topology` messages and whom to notify, before the node is initialized
+pub(crate) struct TopologyHook {
+    replies: Weak<Mutex<dyn Write + Send>>,
+    hook: Hook,
+    pub(crate) strategy: Strategy,
+}
+
+impl TopologyHook {
+    pub(crate) fn new(replies: Weak<Mutex<dyn Write + Send>>, hook: Hook) -> Self {
+        Self {
+            replies,
+            hook,
+            strategy: Strategy::Maelstrom,
+        }
+    }
+
+    /// Build the handler of the `topology` messages of the node, once it is initialized.
+    ///
+    /// Unless the strategy uses the topology suggested by Maelstrom, the topology is computed and
+    /// stored right away, and the hook is notified.
+    pub(crate) fn into_handler(
+        mut self,
+        topology: SharedTopology,
+        msg_ids: MsgIdAllocator,
+        node_ids: &[NodeId],
+    ) -> TopologyHandler {
+        let computed = self.strategy.build(node_ids);
+        let fixed = computed.is_some();
+        *topology.write().unwrap_or_else(|e| e.into_inner()) = computed.clone();
+        if let Some(computed) = &computed {
+            tracing::info!(strategy = %self.strategy, "using a computed topology");
+            (self.hook)(computed);
+        }
+        TopologyHandler {
+            topology,
+            replies: self.replies,
+            msg_ids,
+            hook: self.hook,
+            fixed,
+        }
+    }
+}
+
+impl fmt::Debug for TopologyHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopologyHook")
+            .field("strategy", &self.strategy)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Handles the `topology` messages read by an [`InputInterface`](crate::InputInterface)
+pub(crate) struct TopologyHandler {
+    topology: SharedTopology,
+    /// the writer of the output interface, which may have been dropped
+    replies: Weak<Mutex<dyn Write + Send>>,
+    msg_ids: MsgIdAllocator,
+    hook: Hook,
+    /// whether the topology was computed by the node, and the suggested one is ignored
+    fixed: bool,
+}
+
+impl TopologyHandler {
+    /// Store the topology of a `topology` message, acknowledge it and notify the hook
+    pub(crate) fn handle(&mut self, line: &str) -> anyhow::Result<()> {
+        let msg: Message<TopologyPayload> =
+            serde_json::from_str(line).context("Malformed topology message")?;
+        let TopologyPayload::Topology { topology } = &msg.body.payload else {
+            return Ok(());
+        };
+        if self.fixed {
+            tracing::debug!("ignoring the suggested topology for the computed one");
+        } else {
+            *self.topology.write().unwrap_or_else(|e| e.into_inner()) = Some(topology.clone());
+            (self.hook)(topology);
+        }
+
+        let reply = msg.to_response(Some(self.msg_ids.next_id()), TopologyPayload::TopologyOk);
+        let line = serde_json::to_string(&reply).context("Serializing topology_ok")?;
+        let replies = self
+            .replies
+            .upgrade()
+            .ok_or_else(|| anyhow!("The output interface was dropped"))?;
+        let mut writer = replies.lock().unwrap_or_else(|e| e.into_inner());
+        crate::write_line(&mut *writer, line)
+    }
+}
+
+impl fmt::Debug for TopologyHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopologyHandler")
+            .field("topology", &self.topology)
+            .field("fixed", &self.fixed)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether a message, serialized as json, is a `topology` message
+pub(crate) fn is_topology(line: &str) -> bool {
+    Envelope::parse(line).is_ok_and(|envelope| envelope.body.msg_type == "topology")
+}
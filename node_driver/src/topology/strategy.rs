@@ -0,0 +1,453 @@
+//! Topologies computed by the nodes themselves from the ids of the cluster.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context};
+
+use super::Topology;
+use crate::NodeId;
+
+/// The environment variable read by [`Strategy::from_env`]
+pub const TOPOLOGY_ENV_VAR: &str = "NODE_DRIVER_TOPOLOGY";
+
+/// How the nodes choose their neighbours.
+///
+/// Apart from [`Strategy::Maelstrom`], the topology is computed from the `node_ids` of the `init`
+/// message, in their order, so every node of the cluster computes the same one. Denser topologies
+/// deliver messages in fewer hops at the cost of more messages per broadcast.
+///
+/// Strategies are parsed from their name, followed by their parameters separated by `:`:
+///
+/// ```
+/// use node_driver::topology::Strategy;
+///
+/// assert_eq!("mesh".parse::<Strategy>().unwrap(), Strategy::Mesh);
+/// assert_eq!("tree:4".parse::<Strategy>().unwrap(), Strategy::Tree { arity: 4 });
+/// assert_eq!(
+///     "random:3:42".parse::<Strategy>().unwrap(),
+///     Strategy::RandomRegular { degree: 3, seed: 42 }
+/// );
+/// assert_eq!(Strategy::Tree { arity: 4 }.to_string(), "tree:4");
+/// assert!("tree:0".parse::<Strategy>().is_err());
+/// assert!("random:1".parse::<Strategy>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Use the topology suggested by Maelstrom (`maelstrom`)
+    #[default]
+    Maelstrom,
+    /// Every node is a neighbour of every other node (`mesh`)
+    Mesh,
+    /// Each node is a neighbour of the previous and next ones (`ring`)
+    Ring,
+    /// A tree where each node has up to `arity` children, rooted at the first node
+    /// (`tree[:arity]`, binary by default)
+    Tree {
+        /// the maximum number of children of a node
+        arity: usize,
+    },
+    /// The nodes are laid out row by row on a square grid, each being a neighbour of the nodes
+    /// above, below, left and right of it (`grid`)
+    Grid,
+    /// The first node is the hub, the only neighbour of every other node (`star`)
+    Star,
+    /// A random graph where every node has `degree` neighbours, when the size of the cluster
+    /// allows it (`random[:degree[:seed]]`, 3 and 0 by default). The degree must be at least 2
+    /// for the graph to be connected. Nodes using the same seed compute the same graph.
+    RandomRegular {
+        /// the number of neighbours of each node
+        degree: usize,
+        /// the seed of the random generator
+        seed: u64,
+    },
+    /// The spanning tree of the [`Strategy::Grid`] topology with the smallest diameter, which
+    /// only keeps the grid links on the shortest paths from its center (`spanning_tree`)
+    SpanningTree,
+}
+
+impl Strategy {
+    /// Read the strategy from the [`TOPOLOGY_ENV_VAR`] environment variable, defaulting to
+    /// [`Strategy::Maelstrom`] when it is not set
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var(TOPOLOGY_ENV_VAR) {
+            Ok(strategy) => strategy
+                .parse()
+                .with_context(|| format!("Invalid {TOPOLOGY_ENV_VAR}")),
+            Err(std::env::VarError::NotPresent) => Ok(Self::Maelstrom),
+            Err(e) => Err(e).with_context(|| format!("Invalid {TOPOLOGY_ENV_VAR}")),
+        }
+    }
+
+    /// Compute the topology of the cluster made of the given nodes, or `None` for
+    /// [`Strategy::Maelstrom`]
+    pub fn build(&self, node_ids: &[NodeId]) -> Option<Topology> {
+        Some(match *self {
+            Self::Maelstrom => return None,
+            Self::Mesh => Topology::mesh(node_ids),
+            Self::Ring => Topology::ring(node_ids),
+            Self::Tree { arity } => Topology::tree(node_ids, arity),
+            Self::Grid => Topology::grid(node_ids),
+            Self::Star => Topology::star(node_ids),
+            Self::RandomRegular { degree, seed } => {
+                Topology::random_regular(node_ids, degree, seed)
+            }
+            Self::SpanningTree => Topology::grid(node_ids).min_diameter_spanning_tree(),
+        })
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        let params = parts
+            .map(|param| {
+                param
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid parameter {param:?} of topology {s:?}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let param = |i: usize, default: u64| params.get(i).copied().unwrap_or(default);
+        let max_params = match name {
+            "maelstrom" | "mesh" | "ring" | "grid" | "star" | "spanning_tree" => 0,
+            "tree" => 1,
+            "random" => 2,
+            _ => bail!(
+                "Unknown topology {s:?}, expected one of maelstrom, mesh, ring, tree[:arity], \
+                 grid, star, random[:degree[:seed]] or spanning_tree"
+            ),
+        };
+        if params.len() > max_params {
+            bail!("Too many parameters for topology {s:?}");
+        }
+        Ok(match name {
+            "maelstrom" => Self::Maelstrom,
+            "mesh" => Self::Mesh,
+            "ring" => Self::Ring,
+            "tree" => match param(0, 2) {
+                0 => bail!("The arity of a tree must be at least 1"),
+                arity => Self::Tree {
+                    arity: arity as usize,
+                },
+            },
+            "grid" => Self::Grid,
+            "star" => Self::Star,
+            "random" => match param(0, 3) {
+                0 | 1 => bail!("The degree of a random topology must be at least 2"),
+                degree => Self::RandomRegular {
+                    degree: degree as usize,
+                    seed: param(1, 0),
+                },
+            },
+            _ => Self::SpanningTree,
+        })
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Maelstrom => write!(f, "maelstrom"),
+            Self::Mesh => write!(f, "mesh"),
+            Self::Ring => write!(f, "ring"),
+            Self::Tree { arity } => write!(f, "tree:{arity}"),
+            Self::Grid => write!(f, "grid"),
+            Self::Star => write!(f, "star"),
+            Self::RandomRegular { degree, seed } => write!(f, "random:{degree}:{seed}"),
+            Self::SpanningTree => write!(f, "spanning_tree"),
+        }
+    }
+}
+
+/// Topologies of the [`Strategy`]s, the neighbours of each node being in the order of `node_ids`
+impl Topology {
+    /// Every node is a neighbour of every other node
+    pub fn mesh(node_ids: &[NodeId]) -> Self {
+        Self::from_edges(node_ids, |i, j| i != j)
+    }
+
+    /// Each node is a neighbour of the previous and next ones
+    ///
+    /// ```
+    /// use node_driver::{topology::Topology, NodeId};
+    ///
+    /// let nodes: Vec<NodeId> = vec!["n0".into(), "n1".into(), "n2".into(), "n3".into()];
+    /// let ring = Topology::ring(&nodes);
+    /// assert_eq!(ring.neighbours_of("n0"), ["n1", "n3"]);
+    /// assert_eq!(ring.neighbours_of("n2"), ["n1", "n3"]);
+    /// ```
+    pub fn ring(node_ids: &[NodeId]) -> Self {
+        let n = node_ids.len();
+        Self::from_edges(node_ids, |i, j| {
+            i != j && ((i + 1) % n == j || (j + 1) % n == i)
+        })
+    }
+
+    /// A tree where each node has up to `arity` children, rooted at the first node
+    ///
+    /// ```
+    /// use node_driver::{topology::Topology, NodeId};
+    ///
+    /// let nodes: Vec<NodeId> = (0..7).map(|i| format!("n{i}").into()).collect();
+    /// let tree = Topology::tree(&nodes, 3);
+    /// assert_eq!(tree.neighbours_of("n0"), ["n1", "n2", "n3"]);
+    /// assert_eq!(tree.neighbours_of("n1"), ["n0", "n4", "n5", "n6"]);
+    /// assert_eq!(tree.neighbours_of("n6"), ["n1"]);
+    /// ```
+    pub fn tree(node_ids: &[NodeId], arity: usize) -> Self {
+        let arity = arity.max(1);
+        let is_parent =
+            move |parent: usize, child: usize| child > 0 && (child - 1) / arity == parent;
+        Self::from_edges(node_ids, |i, j| is_parent(i, j) || is_parent(j, i))
+    }
+
+    /// The nodes are laid out row by row on the smallest square grid holding them, each being a
+    /// neighbour of the nodes above, below, left and right of it
+    ///
+    /// ```
+    /// use node_driver::{topology::Topology, NodeId};
+    ///
+    /// let nodes: Vec<NodeId> = (0..5).map(|i| format!("n{i}").into()).collect();
+    /// // n0 n1 n2
+    /// // n3 n4
+    /// let grid = Topology::grid(&nodes);
+    /// assert_eq!(grid.neighbours_of("n1"), ["n0", "n2", "n4"]);
+    /// assert_eq!(grid.neighbours_of("n2"), ["n1"]);
+    /// ```
+    pub fn grid(node_ids: &[NodeId]) -> Self {
+        let side = grid_side(node_ids.len());
+        Self::from_edges(node_ids, |i, j| {
+            let (low, high) = (i.min(j), i.max(j));
+            high - low == side || (high - low == 1 && high % side != 0)
+        })
+    }
+
+    /// The first node is the hub, the only neighbour of every other node
+    pub fn star(node_ids: &[NodeId]) -> Self {
+        Self::from_edges(node_ids, |i, j| i != j && (i == 0 || j == 0))
+    }
+
+    /// A random graph where every node has `degree` neighbours, which is connected when `degree`
+    /// is at least 2.
+    ///
+    /// The nodes are shuffled on a ring, and each is linked to the `degree / 2` closest nodes on
+    /// both sides, and to the opposite node if `degree` is odd. The degree is capped to the size
+    /// of the cluster, and an odd degree is lowered by one on clusters of odd size, where no
+    /// graph of that degree exists. A degree of 1 only pairs the nodes, and a degree of 0 leaves
+    /// them all isolated.
+    ///
+    /// ```
+    /// use node_driver::{topology::Topology, NodeId};
+    ///
+    /// let nodes: Vec<NodeId> = (0..10).map(|i| format!("n{i}").into()).collect();
+    /// let graph = Topology::random_regular(&nodes, 3, 7);
+    /// assert!(nodes.iter().all(|node| graph.neighbours_of(node.as_str()).len() == 3));
+    /// assert_eq!(graph, Topology::random_regular(&nodes, 3, 7));
+    /// ```
+    pub fn random_regular(node_ids: &[NodeId], degree: usize, seed: u64) -> Self {
+        let n = node_ids.len();
+        let mut degree = degree.min(n.saturating_sub(1));
+        if degree % 2 == 1 && n % 2 == 1 {
+            degree -= 1;
+        }
+
+        // Fisher-Yates shuffle of the positions of the nodes on the ring
+        let mut rng = XorShift::new(seed);
+        let mut order: Vec<usize> = (0..n).collect();
+        for i in (1..n).rev() {
+            order.swap(i, rng.below(i + 1));
+        }
+        let mut position = vec![0; n];
+        for (pos, &node) in order.iter().enumerate() {
+            position[node] = pos;
+        }
+
+        Self::from_edges(node_ids, |i, j| {
+            if i == j {
+                return false;
+            }
+            let gap = position[i].abs_diff(position[j]);
+            let distance = gap.min(n - gap);
+            distance <= degree / 2 || (degree % 2 == 1 && 2 * distance == n)
+        })
+    }
+
+    /// The spanning tree of this topology with the smallest diameter.
+    ///
+    /// In an unweighted graph, it is a tree of shortest paths from a node or from both ends of a
+    /// link, whichever is the most central. Nodes which cannot be reached from the center keep
+    /// no neighbour.
+    ///
+    /// ```
+    /// use node_driver::{topology::Topology, NodeId};
+    ///
+    /// let nodes: Vec<NodeId> = (0..9).map(|i| format!("n{i}").into()).collect();
+    /// // n0 n1 n2
+    /// // n3 n4 n5
+    /// // n6 n7 n8
+    /// let tree = Topology::grid(&nodes).min_diameter_spanning_tree();
+    /// assert_eq!(tree.neighbours_of("n4"), ["n1", "n3", "n5", "n7"]);
+    /// assert_eq!(tree.neighbours_of("n0").len(), 1);
+    /// ```
+    pub fn min_diameter_spanning_tree(&self) -> Self {
+        let mut node_ids: Vec<&NodeId> = self.neighbours.keys().collect();
+        node_ids.sort();
+        let index: HashMap<&NodeId, usize> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, i))
+            .collect();
+        let graph: Vec<Vec<usize>> = node_ids
+            .iter()
+            .map(|&id| {
+                let mut neighbours: Vec<usize> = self.neighbours[id]
+                    .iter()
+                    .filter_map(|neighbour| index.get(neighbour).copied())
+                    .collect();
+                neighbours.sort_unstable();
+                neighbours
+            })
+            .collect();
+
+        // the candidate centers are the nodes and the links of the graph
+        let centers = (0..graph.len())
+            .map(|i| vec![i])
+            .chain(graph.iter().enumerate().flat_map(|(i, neighbours)| {
+                neighbours
+                    .iter()
+                    .filter(move |&&j| i < j)
+                    .map(move |&j| vec![i, j])
+            }));
+        let parents = centers
+            .map(|roots| shortest_paths_tree(&graph, &roots))
+            .min_by_key(|parents| tree_diameter(parents))
+            .unwrap_or_default();
+
+        let node_ids: Vec<NodeId> = node_ids.into_iter().cloned().collect();
+        Self::from_edges(&node_ids, |i, j| {
+            parents[i] == Some(j) || parents[j] == Some(i)
+        })
+    }
+
+    /// The topology linking the nodes `i` and `j` of `node_ids` when `linked(i, j)`, which must
+    /// be symmetric
+    fn from_edges(node_ids: &[NodeId], linked: impl Fn(usize, usize) -> bool) -> Self {
+        let neighbours = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let neighbours = node_ids
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| i != j && linked(i, j))
+                    .map(|(_, neighbour)| neighbour.clone())
+                    .collect();
+                (node.clone(), neighbours)
+            })
+            .collect();
+        Self { neighbours }
+    }
+}
+
+/// The number of columns of the smallest square grid holding `n` nodes
+fn grid_side(n: usize) -> usize {
+    let mut side = 1;
+    while side * side < n {
+        side += 1;
+    }
+    side
+}
+
+/// The parent of each node in a breadth-first search from `roots`, the roots being the parents of
+/// each other when there are two of them
+fn shortest_paths_tree(graph: &[Vec<usize>], roots: &[usize]) -> Vec<Option<usize>> {
+    let mut parents = vec![None; graph.len()];
+    let mut visited = vec![false; graph.len()];
+    let mut queue = VecDeque::new();
+    for &root in roots {
+        visited[root] = true;
+        queue.push_back(root);
+    }
+    if let [first, second] = *roots {
+        parents[second] = Some(first);
+    }
+    while let Some(node) = queue.pop_front() {
+        for &neighbour in &graph[node] {
+            if !visited[neighbour] {
+                visited[neighbour] = true;
+                parents[neighbour] = Some(node);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    parents
+}
+
+/// The diameter of the spanning tree given by the parent of each node, counting a node missing
+/// from the tree as infinitely far
+fn tree_diameter(parents: &[Option<usize>]) -> usize {
+    let mut tree = vec![Vec::new(); parents.len()];
+    for (child, parent) in parents.iter().enumerate() {
+        if let Some(parent) = *parent {
+            tree[child].push(parent);
+            tree[parent].push(child);
+        }
+    }
+    // the farthest node from any node is an end of a longest path
+    let (farthest, _) = farthest_node(&tree, 0);
+    let (_, diameter) = farthest_node(&tree, farthest);
+    diameter
+}
+
+/// The node farthest from `start` in a tree and its distance, which is `usize::MAX` if the tree
+/// does not reach every node
+fn farthest_node(tree: &[Vec<usize>], start: usize) -> (usize, usize) {
+    if tree.is_empty() {
+        return (0, 0);
+    }
+    let mut distances = vec![usize::MAX; tree.len()];
+    distances[start] = 0;
+    let mut queue = VecDeque::from([start]);
+    let mut farthest = (start, 0);
+    while let Some(node) = queue.pop_front() {
+        farthest = (node, distances[node]);
+        for &neighbour in &tree[node] {
+            if distances[neighbour] == usize::MAX {
+                distances[neighbour] = distances[node] + 1;
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    if distances.contains(&usize::MAX) {
+        return (farthest.0, usize::MAX);
+    }
+    farthest
+}
+
+/// A small xorshift generator, so that every node draws the same numbers from the same seed
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // the state must not be zero, mix the seed so close seeds give unrelated sequences
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..bound`
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
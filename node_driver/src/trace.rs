@@ -0,0 +1,71 @@
+//! Traces of the messages exchanged by the nodes, as read by [`read_trace`](crate::diagram::read_trace).
+//!
+//! When the [`TRACE_ENV_VAR`] environment variable holds the path of a file, the node appends to
+//! it every message it receives, and every message it sends to a client or a service, as one json
+//! message per line. Messages sent to another node are left to the receiving node, so when all the
+//! nodes of a run trace to the same file, each message appears once, in the order it was
+//! received:
+//!
+//! ```text
+//! $ rm -f trace.jsonl
+//! $ NODE_DRIVER_TRACE=$PWD/trace.jsonl maelstrom test -w broadcast --bin ...
+//! $ trace_diagram --svg < trace.jsonl > messages.svg
+//! $ trace_history < trace.jsonl > history.edn
+//! ```
+//!
+//! Tracing is off when the variable is not set, and a file which cannot be opened is reported
+//! with a warning.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{Envelope, NodeKind};
+
+/// Environment variable holding the path of the file messages are appended to
+pub const TRACE_ENV_VAR: &str = "NODE_DRIVER_TRACE";
+
+/// The trace file, if tracing is on
+fn trace_file() -> Option<&'static Mutex<File>> {
+    static TRACE_FILE: OnceLock<Option<Mutex<File>>> = OnceLock::new();
+    TRACE_FILE
+        .get_or_init(|| {
+            let path = std::env::var_os(TRACE_ENV_VAR)?;
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => Some(Mutex::new(file)),
+                Err(e) => {
+                    tracing::warn!(path = %path.to_string_lossy(), error = %e, "could not open the trace file");
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Record a message received as a json line
+pub(crate) fn record_received(line: &str) {
+    if let Some(file) = trace_file() {
+        append(file, line);
+    }
+}
+
+/// Record a message sent as a json line, unless another node receives it
+pub(crate) fn record_sent(line: &str) {
+    let Some(file) = trace_file() else {
+        return;
+    };
+    if Envelope::parse(line).is_ok_and(|envelope| envelope.dst.kind() != NodeKind::Node) {
+        append(file, line);
+    }
+}
+
+fn append(file: &Mutex<File>, line: &str) {
+    // a single write per line, so that the lines of the nodes sharing the file don't mix
+    let line = format!("{line}\n");
+    let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+    if let Err(e) = file.write_all(line.as_bytes()) {
+        tracing::warn!(error = %e, "could not write to the trace file");
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    io::{self, BufRead, Read},
+    sync::mpsc::Receiver,
+};
+
+/// A [`BufRead`] yielding the lines received on a channel, used by transports gathering messages
+/// from several connections.
+///
+/// The reader reaches EOF once all the senders of the channel have been dropped.
+#[derive(Debug)]
+pub struct ChannelReader {
+    lines: Receiver<String>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl ChannelReader {
+    /// Create a reader yielding the lines received on `lines`, which must not contain newlines
+    pub fn new(lines: Receiver<String>) -> Self {
+        Self {
+            lines,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for ChannelReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position == self.buffer.len() {
+            self.position = 0;
+            self.buffer.clear();
+            // an empty buffer means EOF, which is what we want once the channel is closed
+            while let Ok(line) = self.lines.recv() {
+                if !line.is_empty() {
+                    self.buffer = line.into_bytes();
+                    self.buffer.push(b'\n');
+                    break;
+                }
+            }
+        }
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.buffer.len());
+    }
+}
@@ -0,0 +1,50 @@
+//! Transports carrying messages between a node and the rest of the network.
+//!
+//! Under Maelstrom, nodes talk through stdin and stdout, which is what the [`Stdio`] transport
+//! does and what [`Maelstrom::init`](crate::Maelstrom::init) uses. Other transports let the same
+//! node code run outside of Maelstrom, for instance as a local cluster over TCP with
+//! [`TcpTransport`], or on a single machine through a router with [`UnixTransport`].
+//!
+//! A transport simply provides a reader of incoming json lines and a writer for outgoing ones,
+//! which are wrapped into an [`InputInterface`](crate::InputInterface) and an
+//! [`OutputInterface`](crate::OutputInterface) by [`InitBuilder::from_transport`](crate::InitBuilder::from_transport).
+//! Since every line written is a complete message, the writer can route it according to its
+//! `dest` field.
+
+mod channel;
+mod tcp;
+#[cfg(unix)]
+mod unix;
+
+use std::io::{BufRead, StdinLock, Write};
+
+use crate::stdio::ProtocolStdout;
+
+pub use channel::ChannelReader;
+pub use tcp::{parse_peers, TcpRouter, TcpTransport, LISTEN_ENV_VAR, PEERS_ENV_VAR};
+#[cfg(unix)]
+pub use unix::{UnixRouter, UnixTransport, NODE_ID_ENV_VAR, ROUTER_ENV_VAR};
+
+/// A way to exchange messages with the network
+pub trait Transport {
+    /// Source of incoming messages, one json message per line
+    type Reader: BufRead;
+    /// Destination of outgoing messages, one json message per line
+    type Writer: Write;
+
+    /// Start the transport and obtain its reader and writer
+    fn split(self) -> anyhow::Result<(Self::Reader, Self::Writer)>;
+}
+
+/// The transport used by Maelstrom: messages are read from stdin and written to stdout.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stdio;
+
+impl Transport for Stdio {
+    type Reader = StdinLock<'static>;
+    type Writer = ProtocolStdout;
+
+    fn split(self) -> anyhow::Result<(Self::Reader, Self::Writer)> {
+        Ok((std::io::stdin().lock(), ProtocolStdout::get()))
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use super::{ChannelReader, Transport};
+use crate::NodeId;
+
+/// Environment variable holding the address of every node of the cluster, as a comma separated
+/// list of `node_id=address` (e.g. `n0=127.0.0.1:7000,n1=127.0.0.1:7001`)
+pub const PEERS_ENV_VAR: &str = "NODE_DRIVER_PEERS";
+
+/// Environment variable holding the address a node listens on
+pub const LISTEN_ENV_VAR: &str = "NODE_DRIVER_LISTEN";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A transport running nodes as a local cluster over TCP.
+///
+/// Each node listens on its own address and reads the messages sent by any connection to it.
+/// Messages are sent to other nodes according to a static map of node ids to addresses,
+/// connecting to them on first use. Messages for anyone else, like clients, are sent back through
+/// the connection their last message arrived from.
+///
+/// Like in Maelstrom, the network is unreliable: a message that cannot be delivered to a peer is
+/// dropped with a warning, and so is a message to an unknown destination. Writing a line which is
+/// not a message is an error, reported when the writer is flushed.
+///
+/// ```
+/// use std::io::{BufRead, Write};
+/// use node_driver::transport::{TcpTransport, Transport};
+///
+/// let n0 = TcpTransport::bind("127.0.0.1:0")?;
+/// let n1 = TcpTransport::bind("127.0.0.1:0")?;
+/// let (n0_addr, n1_addr) = (n0.local_addr()?, n1.local_addr()?);
+///
+/// let (_, mut n0_writer) = n0.with_peer("n1", n1_addr).split()?;
+/// let (mut n1_reader, _) = n1.with_peer("n0", n0_addr).split()?;
+///
+/// n0_writer.write_all(b"{\"src\":\"n0\",\"dest\":\"n1\",\"body\":{\"type\":\"gossip\"}}\n")?;
+/// let mut line = String::new();
+/// n1_reader.read_line(&mut line)?;
+/// assert!(line.contains("gossip"));
+///
+/// // nobody is known as c1, the message is lost
+/// n0_writer.write_all(b"{\"src\":\"n0\",\"dest\":\"c1\",\"body\":{\"type\":\"gossip\"}}\n")?;
+/// n0_writer.flush()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct TcpTransport {
+    listener: TcpListener,
+    peers: HashMap<NodeId, SocketAddr>,
+}
+
+impl TcpTransport {
+    /// Listen on the given address, without any peer yet
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> anyhow::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr).context("Binding TCP listener")?,
+            peers: HashMap::new(),
+        })
+    }
+
+    /// Configure the transport from the [`LISTEN_ENV_VAR`] and [`PEERS_ENV_VAR`] environment
+    /// variables
+    pub fn from_env() -> anyhow::Result<Self> {
+        let listen = std::env::var(LISTEN_ENV_VAR)
+            .with_context(|| format!("{LISTEN_ENV_VAR} should hold the address to listen on"))?;
+        let peers = std::env::var(PEERS_ENV_VAR).unwrap_or_default();
+        Ok(Self::bind(listen)?.with_peers(parse_peers(&peers)?))
+    }
+
+    /// The address this transport listens on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Add the address of a peer node
+    pub fn with_peer(mut self, node_id: impl Into<NodeId>, addr: SocketAddr) -> Self {
+        self.peers.insert(node_id.into(), addr);
+        self
+    }
+
+    /// Add the addresses of several peer nodes
+    pub fn with_peers(mut self, peers: impl IntoIterator<Item = (NodeId, SocketAddr)>) -> Self {
+        self.peers.extend(peers);
+        self
+    }
+}
+
+/// Parse a list of peers in the format of [`PEERS_ENV_VAR`]
+pub fn parse_peers(peers: &str) -> anyhow::Result<HashMap<NodeId, SocketAddr>> {
+    peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(|peer| {
+            let Some((node_id, addr)) = peer.split_once('=') else {
+                bail!("Peer {peer} should be formatted as node_id=address");
+            };
+            let addr = addr
+                .parse()
+                .with_context(|| format!("Invalid address for peer {node_id}: {addr}"))?;
+            Ok((node_id.parse()?, addr))
+        })
+        .collect()
+}
+
+impl Transport for TcpTransport {
+    type Reader = ChannelReader;
+    type Writer = TcpRouter;
+
+    fn split(self) -> anyhow::Result<(Self::Reader, Self::Writer)> {
+        let (lines_tx, lines_rx) = mpsc::channel();
+        let inbound = Arc::new(Mutex::new(HashMap::new()));
+
+        let listener = self.listener;
+        let peers = self.peers.clone();
+        let routes = inbound.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let lines_tx = lines_tx.clone();
+                        let peers = peers.clone();
+                        let routes = routes.clone();
+                        thread::spawn(move || read_connection(stream, lines_tx, &peers, &routes));
+                    }
+                    Err(e) => tracing::warn!(error = %e, "failed to accept TCP connection"),
+                }
+            }
+        });
+
+        Ok((
+            ChannelReader::new(lines_rx),
+            TcpRouter {
+                peers: self.peers,
+                outbound: HashMap::new(),
+                inbound,
+                pending: Vec::new(),
+                error: None,
+            },
+        ))
+    }
+}
+
+/// The fields used to route messages
+#[derive(Deserialize)]
+struct Route {
+    src: NodeId,
+    dest: NodeId,
+}
+
+/// Forward the lines of a connection to the reader, and remember the connection as the way back to
+/// the nodes it carries messages from.
+fn read_connection(
+    stream: TcpStream,
+    lines: mpsc::Sender<String>,
+    peers: &HashMap<NodeId, SocketAddr>,
+    routes: &Mutex<HashMap<NodeId, TcpStream>>,
+) {
+    let reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read from TCP connection");
+            return;
+        }
+    };
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if let Ok(route) = serde_json::from_str::<Route>(&line) {
+            if !peers.contains_key(&route.src) {
+                if let Ok(back) = stream.try_clone() {
+                    routes
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .insert(route.src, back);
+                }
+            }
+        }
+        if lines.send(line).is_err() {
+            break;
+        }
+    }
+}
+
+/// The writer of a [`TcpTransport`], sending each line to the connection of its `dest`.
+#[derive(Debug)]
+pub struct TcpRouter {
+    peers: HashMap<NodeId, SocketAddr>,
+    /// connections opened to peers
+    outbound: HashMap<NodeId, TcpStream>,
+    /// connections opened by others, keyed by the `src` of the messages they carry
+    inbound: Arc<Mutex<HashMap<NodeId, TcpStream>>>,
+    /// bytes of an incomplete line
+    pending: Vec<u8>,
+    /// the first error met while routing the lines written, reported on flush
+    error: Option<io::Error>,
+}
+
+impl TcpRouter {
+    fn route(&mut self, line: &[u8]) -> io::Result<()> {
+        let dest = serde_json::from_slice::<Route>(line)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .dest;
+
+        if let Some(&addr) = self.peers.get(&dest) {
+            // retry once with a new connection, since the peer may have closed the previous one
+            for _ in 0..2 {
+                let stream = match self.outbound.get_mut(&dest) {
+                    Some(stream) => stream,
+                    None => match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                        Ok(stream) => {
+                            let _ = stream.set_nodelay(true);
+                            self.outbound.entry(dest.clone()).or_insert(stream)
+                        }
+                        Err(e) => {
+                            tracing::warn!(error = %e, %dest, "cannot connect to peer, dropping message");
+                            return Ok(());
+                        }
+                    },
+                };
+                if write_line(stream, line).is_ok() {
+                    return Ok(());
+                }
+                self.outbound.remove(&dest);
+            }
+            tracing::warn!(%dest, "cannot write to peer, dropping message");
+            return Ok(());
+        }
+
+        let mut inbound = self.inbound.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(stream) = inbound.get_mut(&dest) else {
+            // like a lost message on an unreliable network
+            tracing::warn!(%dest, "no route to destination, dropping message");
+            return Ok(());
+        };
+        if let Err(e) = write_line(stream, line) {
+            tracing::warn!(error = %e, %dest, "connection closed, dropping message");
+            inbound.remove(&dest);
+        }
+        Ok(())
+    }
+}
+
+fn write_line(mut stream: &TcpStream, line: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(line.len() + 1);
+    buf.extend_from_slice(line);
+    buf.push(b'\n');
+    stream.write_all(&buf)
+}
+
+impl Write for TcpRouter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // the bytes are consumed whatever happens to their lines, routing errors are reported on
+        // flush
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = &line[..end];
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                if let Err(e) = self.route(line) {
+                    self.error.get_or_insert(e);
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
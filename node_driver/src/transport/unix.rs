@@ -0,0 +1,288 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::Transport;
+use crate::NodeId;
+
+/// Environment variable holding the path of the socket of the [`UnixRouter`]
+pub const ROUTER_ENV_VAR: &str = "NODE_DRIVER_ROUTER";
+
+/// Environment variable holding the id a node registers with to the [`UnixRouter`]
+pub const NODE_ID_ENV_VAR: &str = "NODE_DRIVER_NODE_ID";
+
+/// First line sent on a connection to the router, to tell who is on the other end
+#[derive(Debug, Serialize, Deserialize)]
+struct Registration {
+    register: NodeId,
+}
+
+#[derive(Deserialize)]
+struct Route {
+    dest: NodeId,
+}
+
+/// A transport connecting a node to a [`UnixRouter`] through a Unix domain socket.
+///
+/// On connection, the node registers to the router under its id, and the router then forwards to
+/// it every message whose `dest` is this id. Clients driving the cluster connect the same way,
+/// using their own client id.
+#[derive(Debug)]
+pub struct UnixTransport {
+    stream: UnixStream,
+}
+
+impl UnixTransport {
+    /// Connect to the router listening on `path`, and register as `node_id`
+    pub fn connect<P: AsRef<Path>>(path: P, node_id: impl Into<NodeId>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut stream = UnixStream::connect(path)
+            .with_context(|| format!("Connecting to router at {}", path.display()))?;
+        let mut registration = serde_json::to_vec(&Registration {
+            register: node_id.into(),
+        })?;
+        registration.push(b'\n');
+        stream
+            .write_all(&registration)
+            .context("Registering to router")?;
+        Ok(Self { stream })
+    }
+
+    /// Connect to the router whose path is in [`ROUTER_ENV_VAR`], registering with the id in
+    /// [`NODE_ID_ENV_VAR`]
+    pub fn from_env() -> anyhow::Result<Self> {
+        let path = std::env::var(ROUTER_ENV_VAR)
+            .with_context(|| format!("{ROUTER_ENV_VAR} should hold the router socket path"))?;
+        let node_id = std::env::var(NODE_ID_ENV_VAR)
+            .with_context(|| format!("{NODE_ID_ENV_VAR} should hold the id of the node"))?;
+        Self::connect(path, node_id.parse::<NodeId>()?)
+    }
+}
+
+impl Transport for UnixTransport {
+    type Reader = BufReader<UnixStream>;
+    type Writer = UnixStream;
+
+    fn split(self) -> anyhow::Result<(Self::Reader, Self::Writer)> {
+        let reader = self.stream.try_clone().context("Cloning router socket")?;
+        Ok((BufReader::new(reader), self.stream))
+    }
+}
+
+/// The maximum number of messages kept for destinations which have not registered yet, further
+/// ones are dropped
+const MAX_PENDING: usize = 10_000;
+
+/// A registered node or client
+struct Connection {
+    /// tells this connection apart from a later one registered under the same id
+    generation: u64,
+    /// the queue of the thread writing to the connection, so routing never waits for a socket
+    queue: mpsc::Sender<String>,
+}
+
+#[derive(Default)]
+struct RouterState {
+    /// connections of registered nodes and clients
+    connections: HashMap<NodeId, Connection>,
+    /// messages waiting for their destination to register
+    pending: HashMap<NodeId, Vec<String>>,
+    /// the number of messages in `pending`
+    pending_len: usize,
+    next_generation: u64,
+}
+
+impl RouterState {
+    /// Queue a message for its destination, or keep it until the destination registers
+    fn route(&mut self, dest: NodeId, line: String) {
+        if let Some(connection) = self.connections.get(&dest) {
+            match connection.queue.send(line) {
+                Ok(()) => return,
+                Err(mpsc::SendError(line)) => {
+                    // the writer of the connection stopped, keep the message for the next one
+                    tracing::warn!(%dest, "connection closed, keeping message until it registers again");
+                    self.connections.remove(&dest);
+                    return self.route(dest, line);
+                }
+            }
+        }
+        if self.pending_len >= MAX_PENDING {
+            tracing::warn!(%dest, "too many messages waiting for their destination, dropping message");
+            return;
+        }
+        self.pending_len += 1;
+        self.pending.entry(dest).or_default().push(line);
+    }
+
+    /// Remove the connection of `id`, unless it was replaced by a newer one
+    fn disconnect(&mut self, id: &NodeId, generation: u64) {
+        if self
+            .connections
+            .get(id)
+            .is_some_and(|connection| connection.generation == generation)
+        {
+            self.connections.remove(id);
+        }
+    }
+}
+
+/// A router forwarding messages between the nodes and clients connected to it with a
+/// [`UnixTransport`], according to their `dest`.
+///
+/// Messages sent to an id which has not registered yet are kept until it does, so nodes can be
+/// started in any order, up to 10 000 messages. Each connection is written to from its own
+/// thread, so a node which does not read its messages never blocks the others. The `node_router` binary runs one as a standalone process.
+///
+/// ```
+/// use std::io::{BufRead, Write};
+/// use node_driver::transport::{Transport, UnixRouter, UnixTransport};
+///
+/// let path = std::env::temp_dir().join(format!("node-driver-doc-{}.sock", std::process::id()));
+/// let router = UnixRouter::bind(&path)?;
+/// router.spawn();
+///
+/// let (_, mut client) = UnixTransport::connect(&path, "c1")?.split()?;
+/// client.write_all(b"{\"src\":\"c1\",\"dest\":\"n1\",\"body\":{\"type\":\"echo\",\"echo\":\"hi\"}}\n")?;
+///
+/// // n1 registers after the message was sent, which is then delivered on registration
+/// let (mut node, _) = UnixTransport::connect(&path, "n1")?.split()?;
+/// let mut line = String::new();
+/// node.read_line(&mut line)?;
+/// assert!(line.contains("\"echo\":\"hi\""));
+/// # std::fs::remove_file(&path)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct UnixRouter {
+    listener: UnixListener,
+    path: PathBuf,
+    state: Arc<Mutex<RouterState>>,
+}
+
+impl UnixRouter {
+    /// Listen on the given path, replacing any leftover socket from a previous run
+    pub fn bind<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => {}
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Binding router socket at {}", path.display()))?;
+        Ok(Self {
+            listener,
+            path,
+            state: Default::default(),
+        })
+    }
+
+    /// The path of the router socket
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accept connections and forward messages, forever
+    pub fn run(self) -> anyhow::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream.context("Accepting connection")?;
+            let state = self.state.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &state) {
+                    tracing::warn!(error = %e, "router connection failed");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Run the router in a background thread
+    pub fn spawn(self) -> JoinHandle<anyhow::Result<()>> {
+        thread::spawn(move || self.run())
+    }
+}
+
+fn handle_connection(stream: UnixStream, state: &Arc<Mutex<RouterState>>) -> anyhow::Result<()> {
+    let mut lines = BufReader::new(stream.try_clone()?).lines();
+    let Some(registration) = lines.next() else {
+        return Ok(());
+    };
+    let id = serde_json::from_str::<Registration>(&registration?)
+        .context("The first line of a connection should be a registration")?
+        .register;
+    tracing::debug!(%id, "registered");
+
+    let (queue, messages) = mpsc::channel::<String>();
+    let generation = {
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        let generation = state.next_generation;
+        state.next_generation += 1;
+        let pending = state.pending.remove(&id).unwrap_or_default();
+        state.pending_len -= pending.len();
+        for line in pending {
+            // the receiver is still there, this cannot fail
+            let _ = queue.send(line);
+        }
+        state
+            .connections
+            .insert(id.clone(), Connection { generation, queue });
+        generation
+    };
+
+    // write to the connection from its own thread, without holding the lock
+    let writer_state = state.clone();
+    let writer_id = id.clone();
+    let mut writer = stream;
+    thread::spawn(move || {
+        for line in messages {
+            if let Err(e) = write_line(&mut writer, &line) {
+                tracing::warn!(error = %e, id = %writer_id, "connection closed, dropping message");
+                writer_state
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .disconnect(&writer_id, generation);
+                break;
+            }
+        }
+    });
+
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let dest = match serde_json::from_str::<Route>(&line) {
+            Ok(route) => route.dest,
+            Err(e) => {
+                tracing::warn!(error = %e, %id, %line, "dropping message without destination");
+                continue;
+            }
+        };
+        state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .route(dest, line);
+    }
+
+    state
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .disconnect(&id, generation);
+    tracing::debug!(%id, "disconnected");
+    Ok(())
+}
+
+fn write_line(writer: &mut UnixStream, line: &str) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(line.len() + 1);
+    buf.extend_from_slice(line.as_bytes());
+    buf.push(b'\n');
+    writer.write_all(&buf)
+}
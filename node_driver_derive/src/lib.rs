@@ -0,0 +1,414 @@
+//! Derive macros of the `node_driver` crate, see `node_driver::MaelstromPayload`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, spanned::Spanned, Attribute, Data, DataEnum, DataStruct, DeriveInput,
+    Fields, Ident, LitStr, Type,
+};
+
+/// Implement `Serialize`, `Deserialize` and `node_driver::MaelstromPayload` for a payload enum or
+/// struct, following the Maelstrom conventions.
+///
+/// See the documentation of `node_driver::MaelstromPayload`.
+#[proc_macro_derive(MaelstromPayload, attributes(serde, maelstrom))]
+pub fn derive_maelstrom_payload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// A variant of the payload enum
+struct Variant {
+    ident: Ident,
+    /// the value of the `type` field of the variant
+    msg_type: String,
+    /// the `type` of the reply to this variant, if set explicitly
+    reply: Option<String>,
+    serde_attrs: Vec<Attribute>,
+    fields: TokenStream2,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "MaelstromPayload cannot be derived for generic types",
+        ));
+    }
+    match &input.data {
+        Data::Enum(data) => expand_enum(&input, data),
+        Data::Struct(data) => expand_struct(&input, data),
+        Data::Union(_) => Err(syn::Error::new(
+            input.ident.span(),
+            "MaelstromPayload can only be derived for enums and structs",
+        )),
+    }
+}
+
+fn expand_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    // variants are renamed to snake case, unless the enum sets its own `rename_all`
+    let (rename_all, default_rename_all) = match serde_rename_all(&input.attrs)? {
+        Some(rename_all) => (rename_all, quote!()),
+        None => (RenameAll::Snake, quote!(rename_all = "snake_case")),
+    };
+    let variants = data
+        .variants
+        .iter()
+        .map(|variant| {
+            let mut reply = None;
+            for attr in &variant.attrs {
+                if attr.path().is_ident("maelstrom") {
+                    attr.parse_nested_meta(|meta| {
+                        if meta.path.is_ident("reply") {
+                            reply = Some(meta.value()?.parse::<LitStr>()?.value());
+                            Ok(())
+                        } else {
+                            Err(meta.error("unknown maelstrom attribute, expected `reply`"))
+                        }
+                    })?;
+                }
+            }
+            Ok(Variant {
+                ident: variant.ident.clone(),
+                msg_type: serde_rename(&variant.attrs)?
+                    .unwrap_or_else(|| rename_all.apply(&variant.ident.to_string())),
+                reply,
+                serde_attrs: serde_attrs(&variant.attrs),
+                fields: shadow_fields(&variant.fields),
+            })
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    // the suffix turning the `type` of `Foo` into the one of `FooOk`, like `_ok` in snake case
+    let ok_suffix = rename_all.apply("AOk")[rename_all.apply("A").len()..].to_string();
+    let replies = variants.iter().filter_map(|variant| {
+        let reply = variant.reply.clone().or_else(|| {
+            let reply = format!("{}{ok_suffix}", variant.msg_type);
+            variants
+                .iter()
+                .any(|v| v.msg_type == reply)
+                .then_some(reply)
+        })?;
+        let request = &variant.msg_type;
+        Some(quote!((#request, #reply)))
+    });
+    let types = variants.iter().map(|v| &v.msg_type);
+    let msg_type_arms = variants.iter().map(|variant| {
+        let (ident, msg_type) = (&variant.ident, &variant.msg_type);
+        quote!(Self::#ident { .. } => #msg_type)
+    });
+    let shadow_variants = variants.iter().map(
+        |Variant {
+             ident,
+             serde_attrs,
+             fields,
+             ..
+         }| quote!(#(#serde_attrs)* #ident #fields),
+    );
+
+    let container_attrs = serde_attrs(&input.attrs);
+    let serde_impls = serde_impls(
+        name,
+        quote! {
+            #[serde(tag = "type", #default_rename_all)]
+            #(#container_attrs)*
+            enum
+        },
+        quote!({ #(#shadow_variants,)* }),
+        None,
+    );
+    Ok(quote! {
+        const _: () = {
+            #serde_impls
+
+            impl ::node_driver::MaelstromPayload for #name {
+                const TYPES: &'static [&'static str] = &[#(#types),*];
+                const REPLIES: &'static [(&'static str, &'static str)] = &[#(#replies),*];
+
+                fn msg_type(&self) -> &'static str {
+                    match self {
+                        #(#msg_type_arms,)*
+                    }
+                }
+            }
+        };
+    })
+}
+
+fn expand_struct(input: &DeriveInput, data: &DataStruct) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    if let Fields::Unnamed(fields) = &data.fields {
+        return Err(syn::Error::new(
+            fields.span(),
+            "MaelstromPayload cannot be derived for tuple structs, use named fields",
+        ));
+    }
+
+    let mut response: Option<Type> = None;
+    for attr in &input.attrs {
+        if attr.path().is_ident("maelstrom") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("response") {
+                    response = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown maelstrom attribute, expected `response`"))
+                }
+            })?;
+        }
+    }
+
+    // the `type` of a struct is its name, unless it is renamed
+    let (msg_type, rename) = match serde_rename(&input.attrs)? {
+        Some(rename) => (rename, quote!()),
+        None => {
+            let msg_type = snake_case(&name.to_string());
+            let rename = quote!(#[serde(rename = #msg_type)]);
+            (msg_type, rename)
+        }
+    };
+    let container_attrs = serde_attrs(&input.attrs);
+    let serde_impls = serde_impls(
+        name,
+        quote! {
+            #[serde(tag = "type")]
+            #rename
+            #(#container_attrs)*
+            struct
+        },
+        // unit structs are built with `Name {}` by the remote implementations
+        match &data.fields {
+            Fields::Unit => quote!({}),
+            fields => shadow_fields(fields),
+        },
+        Some(&msg_type),
+    );
+
+    let (replies, request_impl) = match &response {
+        Some(response) => (
+            quote!((#msg_type, <#response as ::node_driver::MaelstromPayload>::TYPES[0])),
+            quote! {
+                impl ::node_driver::Request for #name {
+                    type Response = #response;
+                }
+            },
+        ),
+        None => (quote!(), quote!()),
+    };
+    Ok(quote! {
+        const _: () = {
+            #serde_impls
+
+            impl ::node_driver::MaelstromPayload for #name {
+                const TYPES: &'static [&'static str] = &[#msg_type];
+                const REPLIES: &'static [(&'static str, &'static str)] = &[#replies];
+
+                fn msg_type(&self) -> &'static str {
+                    #msg_type
+                }
+            }
+
+            #request_impl
+        };
+    })
+}
+
+/// `Serialize` and `Deserialize` implementations for `name`, delegating to a shadow type deriving
+/// them remotely.
+///
+/// `header` holds the serde attributes and the keyword of the shadow type, and `body` its
+/// variants or fields.
+///
+/// serde ignores the `type` tag of a struct when deserializing it, so a struct payload, whose
+/// `type` is `msg_type`, is deserialized as the only variant of a tagged enum, which rejects the
+/// other types.
+fn serde_impls(
+    name: &Ident,
+    header: TokenStream2,
+    body: TokenStream2,
+    msg_type: Option<&str>,
+) -> TokenStream2 {
+    let shadow = format_ident!("__{}MaelstromPayload", name);
+    let remote = name.to_string();
+    let deserialize = match msg_type {
+        Some(msg_type) => {
+            let tagged = format_ident!("__{}MaelstromType", name);
+            let deserialize_with = format!("{shadow}::deserialize");
+            quote! {
+                #[derive(serde::Deserialize)]
+                #[serde(crate = "::node_driver::__private::serde", tag = "type")]
+                enum #tagged {
+                    #[serde(rename = #msg_type)]
+                    Payload(#[serde(deserialize_with = #deserialize_with)] #name),
+                }
+
+                let #tagged::Payload(payload) = #tagged::deserialize(deserializer)?;
+                Ok(payload)
+            }
+        }
+        None => quote!(#shadow::deserialize(deserializer)),
+    };
+    quote! {
+        use ::node_driver::__private::serde;
+
+        #[derive(serde::Serialize, serde::Deserialize)]
+        #[serde(crate = "::node_driver::__private::serde", remote = #remote)]
+        #[allow(dead_code)]
+        #header #shadow #body
+
+        impl serde::Serialize for #name {
+            fn serialize<S>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                #shadow::serialize(self, serializer)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for #name {
+            fn deserialize<D>(deserializer: D) -> ::core::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                #deserialize
+            }
+        }
+    }
+}
+
+/// The fields of a variant or struct, with only their serde attributes
+fn shadow_fields(fields: &Fields) -> TokenStream2 {
+    let shadow = fields.iter().map(|field| {
+        let attrs = serde_attrs(&field.attrs);
+        let ty = &field.ty;
+        match &field.ident {
+            Some(ident) => quote!(#(#attrs)* #ident: #ty),
+            None => quote!(#(#attrs)* #ty),
+        }
+    });
+    match fields {
+        Fields::Named(_) => quote!({ #(#shadow,)* }),
+        Fields::Unnamed(_) => quote!(( #(#shadow,)* )),
+        Fields::Unit => quote!(),
+    }
+}
+
+fn serde_attrs(attrs: &[Attribute]) -> Vec<Attribute> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("serde"))
+        .cloned()
+        .collect()
+}
+
+/// The value of a `#[serde(rename = "...")]` attribute, if any
+fn serde_rename(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut rename = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                if let Ok(value) = meta.value() {
+                    rename = Some(value.parse::<LitStr>()?.value());
+                    return Ok(());
+                }
+            }
+            // leave the other serde attributes to serde
+            skip_meta_value(&meta)
+        })?;
+    }
+    Ok(rename)
+}
+
+/// The rule of a `#[serde(rename_all = "...")]` attribute, if any.
+///
+/// The rule must be the same for serialization and deserialization, since it gives the `type` of
+/// the messages.
+fn serde_rename_all(attrs: &[Attribute]) -> syn::Result<Option<RenameAll>> {
+    let mut rename_all = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if !meta.path.is_ident("rename_all") {
+                return skip_meta_value(&meta);
+            }
+            if !meta.input.peek(syn::Token![=]) {
+                return Err(meta.error(
+                    "MaelstromPayload needs the same `rename_all` rule for serialization and \
+                     deserialization, use `rename_all = \"...\"`",
+                ));
+            }
+            let rule = meta.value()?.parse::<LitStr>()?;
+            rename_all = Some(RenameAll::parse(&rule)?);
+            Ok(())
+        })?;
+    }
+    Ok(rename_all)
+}
+
+/// A case convention of serde's `rename_all` attribute
+#[derive(Clone, Copy)]
+enum RenameAll {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+    ScreamingKebab,
+}
+
+impl RenameAll {
+    fn parse(rule: &LitStr) -> syn::Result<Self> {
+        Ok(match rule.value().as_str() {
+            "lowercase" => Self::Lower,
+            "UPPERCASE" => Self::Upper,
+            "PascalCase" => Self::Pascal,
+            "camelCase" => Self::Camel,
+            "snake_case" => Self::Snake,
+            "SCREAMING_SNAKE_CASE" => Self::ScreamingSnake,
+            "kebab-case" => Self::Kebab,
+            "SCREAMING-KEBAB-CASE" => Self::ScreamingKebab,
+            _ => return Err(syn::Error::new(rule.span(), "unknown rename_all rule")),
+        })
+    }
+
+    /// Rename a variant, whose name is in pascal case, like serde does
+    fn apply(self, variant: &str) -> String {
+        match self {
+            Self::Lower => variant.to_ascii_lowercase(),
+            Self::Upper => variant.to_ascii_uppercase(),
+            Self::Pascal => variant.to_string(),
+            Self::Camel => variant[..1].to_ascii_lowercase() + &variant[1..],
+            Self::Snake => snake_case(variant),
+            Self::ScreamingSnake => snake_case(variant).to_ascii_uppercase(),
+            Self::Kebab => snake_case(variant).replace('_', "-"),
+            Self::ScreamingKebab => snake_case(variant).replace('_', "-").to_ascii_uppercase(),
+        }
+    }
+}
+
+/// Consume the value of a nested meta we do not look at, like `default` or `with = "module"`
+fn skip_meta_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|nested| skip_meta_value(&nested))?;
+    }
+    Ok(())
+}
+
+/// Convert a type or variant name to snake case, like serde's `rename_all = "snake_case"`
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, ch) in name.char_indices() {
+        if i > 0 && ch.is_uppercase() {
+            snake.push('_');
+        }
+        snake.push(ch.to_ascii_lowercase());
+    }
+    snake
+}